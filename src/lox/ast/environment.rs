@@ -30,10 +30,40 @@ impl Environment {
             let mut env = enclosing_env.lock().unwrap();
             env.assign(name, value)
         } else {
-            Err(RuntimeError{token: name.clone(), message: format!("Can't assign to undefined variable '{}'", name.lexeme)})
+            Err(RuntimeError::new(name.clone(), format!("Can't assign to undefined variable '{}'", name.lexeme)))
         }
     }
 
+    // every name visible from here with its value, innermost scope first and
+    // alphabetical within a scope; shadowed outer names are left out. The
+    // walk outwards stops before `stop_at`, if it's given.
+    pub fn bindings(&self, stop_at: Option<&Arc<Mutex<Environment>>>) -> Vec<(String, Value)> {
+        let mut bindings: Vec<(String, Value)> = self.values.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(ref enclosing_env) = self.enclosing_env {
+            if stop_at.is_some_and(|stop_at| Arc::ptr_eq(enclosing_env, stop_at)) {
+                return bindings;
+            }
+            for (name, value) in enclosing_env.lock().unwrap().bindings(stop_at) {
+                if !bindings.iter().any(|(seen, _)| *seen == name) {
+                    bindings.push((name, value));
+                }
+            }
+        }
+        bindings
+    }
+
+    // this scope's own variables and the one around it, for heap.rs
+    pub fn values(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.values.iter()
+    }
+
+    pub fn enclosing(&self) -> Option<&Arc<Mutex<Environment>>> {
+        self.enclosing_env.as_ref()
+    }
+
     pub fn get(&self, name: &Token) -> Result<Value> {
         if self.values.contains_key(&name.lexeme) {
             Ok(self.values.get(&name.lexeme).unwrap().clone())
@@ -41,7 +71,7 @@ impl Environment {
             let env = enclosing_env.lock().unwrap();
             env.get(name)
         } else {
-            Err(RuntimeError{token: name.clone(), message: format!("Undefined variable '{}'", name.lexeme)})
+            Err(RuntimeError::new(name.clone(), format!("Undefined variable '{}'", name.lexeme)))
         }
     }
 }
\ No newline at end of file
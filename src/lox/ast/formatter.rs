@@ -0,0 +1,597 @@
+use crate::scanner::{ScanError, Scanner};
+use crate::token_type::{Comment, Token};
+
+use super::parser::{ParseError, Parser};
+use super::{Assign, Binary, Call, Conditional, Expr, ExprVisitor, ForInStmt, ForStmt, FuncStmt, Grouping, IfStmt, Index, IndexAssign, Lambda, ListExpr, Literal, Logical, LoopJump, MapExpr, ReturnStmt, Stmt, StmtVisitor, ThrowStmt, TryStmt, Unary, Variable, WhileStmt};
+
+const INDENT: &str = "    ";
+// calls whose argument list would run past this column get one argument per line
+const MAX_WIDTH: usize = 80;
+
+#[derive(Debug)]
+pub enum FormatError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    // a comment inside an expression or a parameter list, on this line.
+    // Those have nowhere to go but after the statement.
+    Comment(usize),
+}
+
+// scan + parse + format in one go. Errs if the source didn't scan/parse
+// cleanly - we'd rather not format than silently drop half a program.
+pub fn format_source(source: &str) -> Result<String, FormatError> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens();
+    let errors = scanner.errors();
+    if !errors.is_empty() {
+        return Err(FormatError::Scan(errors));
+    }
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse().map_err(FormatError::Parse)?;
+    let mut formatter = Formatter::new(scanner.comments(), parser.stmt_spans());
+    formatter.format(&stmts)
+}
+
+// Pretty printer that writes the AST back out as canonical Lox source.
+// Statements are written straight into `out`; expressions come back from the
+// ExprVisitor as strings (Printer-style) and get written by the statement that
+// owns them.
+pub struct Formatter {
+    comments: Vec<Comment>,
+    next_comment: usize,
+    // see Parser::stmt_spans - consumed in the same pre-order as we visit
+    spans: Vec<(usize, usize)>,
+    next_span: usize,
+    indent: usize,
+    // source line of the last statement/comment written, so single blank
+    // lines between statements survive formatting
+    last_line: usize,
+    // the furthest source line written out so far; a comment from before
+    // it would be moved
+    written: usize,
+    // the line of a block that starts and ends on it, while we're inside.
+    // A trailing comment there comes after the last token, so it's left for
+    // whatever writes that.
+    held: Option<usize>,
+    misplaced: Option<usize>,
+    out: String,
+}
+
+impl Formatter {
+    pub fn new(comments: Vec<Comment>, spans: Vec<(usize, usize)>) -> Self {
+        Self {
+            comments,
+            next_comment: 0,
+            spans,
+            next_span: 0,
+            indent: 0,
+            last_line: 0,
+            written: 0,
+            held: None,
+            misplaced: None,
+            out: String::new(),
+        }
+    }
+
+    pub fn format(&mut self, stmts: &Vec<Stmt>) -> Result<String, FormatError> {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.flush_comments(usize::MAX);
+        match self.misplaced {
+            Some(line) => Err(FormatError::Comment(line)),
+            None => Ok(std::mem::take(&mut self.out)),
+        }
+    }
+
+    // a statement on its own line(s), with any comments that came before it
+    fn statement(&mut self, stmt: &Stmt) {
+        let (start, end) = self.take_span();
+        self.newline();
+        self.flush_comments(start);
+        self.blank_line(start);
+        self.last_line = start;
+        self.wrote(start);
+        self.visit_statement(stmt);
+        self.trailing_comment(end);
+        self.newline();
+        self.last_line = end;
+        self.wrote(end);
+    }
+
+    // body of an if/while/for: blocks stay on the same line as the header,
+    // anything else goes on the next line, indented.
+    fn branch(&mut self, stmt: &Stmt) {
+        if let Stmt::Block(statements) = stmt {
+            let (start, end) = self.take_span();
+            self.write(" ");
+            self.block(statements, start, end);
+        } else {
+            self.newline();
+            self.indent += 1;
+            self.statement(stmt);
+            self.indent -= 1;
+        }
+    }
+
+    // "{ ... }" with the statements indented one level. `start`/`end` are the
+    // lines of the braces in the source.
+    fn block(&mut self, statements: &Vec<Stmt>, start: usize, end: usize) {
+        let held = self.held;
+        if start == end {
+            self.held = Some(start);
+        }
+        self.write("{");
+        self.trailing_comment(start);
+        self.last_line = start;
+        self.wrote(start);
+        if statements.is_empty() && !self.comment_before(end) {
+            self.write("}");
+        } else {
+            self.newline();
+            self.indent += 1;
+            for stmt in statements {
+                self.statement(stmt);
+            }
+            self.flush_comments(end);
+            self.indent -= 1;
+            self.newline();
+            self.write("}");
+        }
+        self.wrote(end);
+        self.held = held;
+    }
+
+    fn wrote(&mut self, line: usize) {
+        self.written = self.written.max(line);
+    }
+
+    fn take_span(&mut self) -> (usize, usize) {
+        let span = self.spans[self.next_span];
+        self.next_span += 1;
+        span
+    }
+
+    fn comment_before(&self, line: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|comment| comment.line < line)
+    }
+
+    // write every remaining comment from before `line`, each on its own line
+    fn flush_comments(&mut self, line: usize) {
+        while self.comment_before(line) {
+            let comment = self.comments[self.next_comment].clone();
+            self.next_comment += 1;
+            if comment.line < self.written && self.misplaced.is_none() {
+                self.misplaced = Some(comment.line);
+            }
+            self.newline();
+            self.blank_line(comment.line);
+            self.write(&comment.text);
+            self.newline();
+            self.last_line = comment.line;
+        }
+    }
+
+    fn trailing_comment(&mut self, line: usize) {
+        if self.held == Some(line) {
+            return;
+        }
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.trailing && comment.line == line && !self.at_line_start() {
+                let text = format!(" {}", comment.text);
+                self.next_comment += 1;
+                self.write(&text);
+            }
+        }
+    }
+
+    // keep (at most one) blank line where the source had one
+    fn blank_line(&mut self, line: usize) {
+        if self.last_line > 0 && line > self.last_line + 1 && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn newline(&mut self) {
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start() {
+            self.out.push_str(&INDENT.repeat(self.indent));
+        }
+        self.out.push_str(text);
+    }
+
+    fn column(&self) -> usize {
+        if self.at_line_start() {
+            return self.indent * INDENT.len();
+        }
+        match self.out.rfind('\n') {
+            Some(i) => self.out.len() - i - 1,
+            None => self.out.len(),
+        }
+    }
+
+    // `prefix(a, b)` for calls, `[a, b]` for lists, `{k: v}` for maps. Kept on
+    // one line when it fits, otherwise one item per line, one level deeper
+    // than the line it started on. `format` lays out the items at whatever
+    // the indent is when it's called.
+    //
+    // A lambda with a block body is the one item that can take up several
+    // lines; as the last item it stays where it is, `map(xs, fun (x) {`.
+    fn delimited(&mut self, prefix: String, (open, close): (char, char), format: impl Fn(&mut Self) -> Vec<String>) -> String {
+        // formatting a lambda uses up spans and comments, so a second go
+        // has to start from the same place
+        let mark = (self.next_span, self.next_comment, self.last_line, self.written);
+        let parts = format(self);
+        let flat = format!("{prefix}{open}{}{close}", parts.join(", "));
+        let first_line = flat.lines().next().unwrap_or("");
+        let only_last_spans_lines = parts.iter().rev().skip(1).all(|part| !part.contains('\n'));
+        if parts.is_empty() || (only_last_spans_lines && self.column() + first_line.len() <= MAX_WIDTH) {
+            return flat;
+        }
+
+        (self.next_span, self.next_comment, self.last_line, self.written) = mark;
+        self.indent += 1;
+        let parts = format(self);
+        let item_indent = INDENT.repeat(self.indent);
+        self.indent -= 1;
+        let mut s = format!("{prefix}{open}\n");
+        s.push_str(&parts.iter().map(|part| format!("{item_indent}{part}")).collect::<Vec<String>>().join(",\n"));
+        s.push('\n');
+        s.push_str(&INDENT.repeat(self.indent));
+        s.push(close);
+        s
+    }
+
+    // `outer: ` in front of a labelled loop
+    fn label(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            self.write(&format!("{}: ", label.lexeme));
+        }
+    }
+
+    fn jump(&mut self, jump: &LoopJump) {
+        match &jump.label {
+            Some(label) => self.write(&format!("{} {};", jump.keyword.lexeme, label.lexeme)),
+            None => self.write(&format!("{};", jump.keyword.lexeme)),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        self.visit_expr(expr)
+    }
+}
+
+impl StmtVisitor<()> for Formatter {
+    fn visit_expr_statement(&mut self, expr: &Expr) {
+        let expr = self.expr(expr);
+        self.write(&format!("{expr};"));
+    }
+
+    fn visit_print_statement(&mut self, expr: &Expr) {
+        self.write("print ");
+        let expr = self.expr(expr);
+        self.write(&format!("{expr};"));
+    }
+
+    fn visit_block_statement(&mut self, statements: &Vec<Stmt>) {
+        // the span was taken by whoever called us, but we need its lines
+        let (start, end) = self.spans[self.next_span - 1];
+        self.block(statements, start, end);
+    }
+
+    fn visit_while_statement(&mut self, while_stmt: &WhileStmt) {
+        self.label(&while_stmt.label);
+        self.write("while (");
+        let condition = self.expr(&while_stmt.condition);
+        self.write(&format!("{condition})"));
+        self.branch(&while_stmt.body);
+    }
+
+    fn visit_for_statement(&mut self, for_stmt: &ForStmt) {
+        self.label(&for_stmt.label);
+        self.write("for (");
+        match &for_stmt.initializer {
+            Some(initializer) => {
+                // var/expression statement - writes its own ';'
+                self.take_span();
+                self.visit_statement(initializer);
+            }
+            None => self.write(";"),
+        }
+        if let Some(condition) = &for_stmt.condition {
+            let condition = self.expr(condition);
+            self.write(&format!(" {condition}"));
+        }
+        self.write(";");
+        if let Some(increment) = &for_stmt.increment {
+            let increment = self.expr(increment);
+            self.write(&format!(" {increment}"));
+        }
+        self.write(")");
+        self.branch(&for_stmt.body);
+    }
+
+    fn visit_for_in_statement(&mut self, for_in: &ForInStmt) {
+        self.label(&for_in.label);
+        let iterable = self.expr(&for_in.iterable);
+        self.write(&format!("for (var {} in {iterable})", for_in.name.lexeme));
+        self.branch(&for_in.body);
+    }
+
+    fn visit_func_statement(&mut self, func_stmt: &FuncStmt) {
+        let (start, end) = self.spans[self.next_span - 1];
+        let parameters: Vec<&str> = func_stmt.parameters.iter().map(|p| p.lexeme.as_str()).collect();
+        if let Some(last) = func_stmt.parameters.last() {
+            self.wrote(last.line);
+        }
+        self.write(&format!("fun {}({}) ", func_stmt.name.lexeme, parameters.join(", ")));
+        self.block(&func_stmt.body, start, end);
+    }
+
+    fn visit_throw_statement(&mut self, throw: &ThrowStmt) {
+        self.write("throw ");
+        let value = self.expr(&throw.value);
+        self.write(&format!("{value};"));
+    }
+
+    fn visit_try_statement(&mut self, try_stmt: &TryStmt) {
+        // each block has its own span after the statement's
+        let (start, end) = self.take_span();
+        self.write("try ");
+        self.block(&try_stmt.body, start, end);
+        if let Some(catch) = &try_stmt.catch {
+            let (start, end) = self.take_span();
+            self.write(&format!(" catch ({}) ", catch.name.lexeme));
+            self.block(&catch.body, start, end);
+        }
+        if let Some(finally) = &try_stmt.finally {
+            let (start, end) = self.take_span();
+            self.write(" finally ");
+            self.block(finally, start, end);
+        }
+    }
+
+    fn visit_var_statement(&mut self, var: &Variable) {
+        if let Expr::Null = var.initializer {
+            self.write(&format!("var {};", var.name.lexeme));
+        } else {
+            self.write(&format!("var {} = ", var.name.lexeme));
+            let initializer = self.expr(&var.initializer);
+            self.write(&format!("{initializer};"));
+        }
+    }
+
+    fn visit_return_statement(&mut self, return_stmt: &ReturnStmt) {
+        if let Expr::Null = return_stmt.value {
+            self.write("return;");
+        } else {
+            let value = self.expr(&return_stmt.value);
+            self.write(&format!("return {value};"));
+        }
+    }
+
+    fn visit_break_statement(&mut self, jump: &LoopJump) {
+        self.jump(jump);
+    }
+
+    fn visit_continue_statement(&mut self, jump: &LoopJump) {
+        self.jump(jump);
+    }
+
+    fn visit_if_statement(&mut self, if_stmt: &IfStmt) {
+        self.write("if (");
+        let condition = self.expr(&if_stmt.condition);
+        self.write(&format!("{condition})"));
+        self.branch(&if_stmt.if_branch);
+        if let Some(else_branch) = &if_stmt.else_branch {
+            // "} else" after a block, "else" on its own line otherwise
+            if self.at_line_start() {
+                self.write("else");
+            } else {
+                self.write(" else");
+            }
+            // last_line is still the then branch's, which would put a blank
+            // line under the `else` when the branch is on the line after it
+            self.last_line = 0;
+            if let Stmt::If(else_if) = else_branch.as_ref() {
+                self.take_span();
+                self.write(" ");
+                self.visit_if_statement(else_if);
+            } else {
+                self.branch(else_branch);
+            }
+        }
+    }
+}
+
+impl ExprVisitor<String> for Formatter {
+    fn visit_assignment(&mut self, assignment: &Assign) -> String {
+        format!("{} {} {}", assignment.name.lexeme, assignment.operator.lexeme, self.expr(&assignment.value))
+    }
+
+    fn visit_conditional(&mut self, conditional: &Conditional) -> String {
+        format!(
+            "{} ? {} : {}",
+            self.expr(&conditional.condition),
+            self.expr(&conditional.then_branch),
+            self.expr(&conditional.else_branch)
+        )
+    }
+
+    fn visit_logical(&mut self, logical: &Logical) -> String {
+        format!("{} {} {}", self.expr(&logical.left), logical.operator.lexeme, self.expr(&logical.right))
+    }
+
+    fn visit_binary(&mut self, binary: &Binary) -> String {
+        format!("{} {} {}", self.expr(&binary.left), binary.operator.lexeme, self.expr(&binary.right))
+    }
+
+    fn visit_unary(&mut self, unary: &Unary) -> String {
+        let right = self.expr(&unary.right);
+        // `- -1`, not `--1`
+        let space = if right.starts_with(&unary.operator.lexeme) { " " } else { "" };
+        format!("{}{space}{right}", unary.operator.lexeme)
+    }
+
+    fn visit_call(&mut self, call: &Call) -> String {
+        let mut callee = self.expr(&call.callee);
+        if call.optional.is_some() {
+            callee += "?.";
+        }
+        self.delimited(callee, ('(', ')'), |f| call.arguments.iter().map(|arg| f.expr(arg)).collect())
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) -> String {
+        let parameters: Vec<&str> = lambda.parameters.iter().map(|p| p.lexeme.as_str()).collect();
+        let parameters = parameters.join(", ");
+        if let ([Stmt::Return(ReturnStmt { value, .. })], true) = (&lambda.body[..], lambda.arrow) {
+            return format!("({parameters}) => {}", self.expr(value));
+        }
+        // the body's statements are written out like any others, just into
+        // a buffer of their own that then becomes this expression's text
+        let (start, end) = self.take_span();
+        self.wrote(lambda.keyword.line);
+        if let Some(last) = lambda.parameters.last() {
+            self.wrote(last.line);
+        }
+        let enclosing = std::mem::replace(&mut self.out, format!("fun ({parameters}) "));
+        self.block(&lambda.body, start, end);
+        std::mem::replace(&mut self.out, enclosing)
+    }
+
+    fn visit_list(&mut self, list: &ListExpr) -> String {
+        self.delimited(String::new(), ('[', ']'), |f| list.elements.iter().map(|element| f.expr(element)).collect())
+    }
+
+    fn visit_map(&mut self, map: &MapExpr) -> String {
+        self.delimited(String::new(), ('{', '}'), |f| {
+            map.entries.iter().map(|(key, value)| format!("{}: {}", f.expr(key), f.expr(value))).collect()
+        })
+    }
+
+    fn visit_index(&mut self, index: &Index) -> String {
+        let optional = if index.optional.is_some() { "?." } else { "" };
+        format!("{}{optional}[{}]", self.expr(&index.object), self.expr(&index.index))
+    }
+
+    fn visit_index_assign(&mut self, index_assign: &IndexAssign) -> String {
+        format!(
+            "{}[{}] {} {}",
+            self.expr(&index_assign.object),
+            self.expr(&index_assign.index),
+            index_assign.operator.lexeme,
+            self.expr(&index_assign.value)
+        )
+    }
+
+    fn visit_grouping(&mut self, grouping: &Grouping) -> String {
+        format!("({})", self.expr(&grouping.0))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> String {
+        match literal {
+            Literal::String(val) => format!("\"{val}\""),
+            Literal::Number(val) => val.to_string(),
+            Literal::Nil => "nil".to_owned(),
+            Literal::Boolean(val) => val.to_string()
+        }
+    }
+
+    fn visit_variable(&mut self, token: &Token) -> String {
+        token.lexeme.clone()
+    }
+
+    fn visit_null(&mut self) -> String { "nil".to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_source, FormatError};
+
+    fn format(source: &str) -> String {
+        format_source(source).unwrap()
+    }
+
+    #[test]
+    fn test_formatter_layout() {
+        let source = "var x=1;fun add(a,b){print a+b;}\nif(x==1){add(x,-2);}else print\"no\";\nfor(var i=0;i<3;i=i+1)print i;";
+        let expected = "var x = 1;\n\
+                        fun add(a, b) {\n    print a + b;\n}\n\
+                        if (x == 1) {\n    add(x, -2);\n} else\n    print \"no\";\n\
+                        for (var i = 0; i < 3; i = i + 1)\n    print i;\n";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+        let once = format("if (a) print 1; else if (b) print 2; else print 3;");
+        assert_eq!(once, "if (a)\n    print 1;\nelse if (b)\n    print 2;\nelse\n    print 3;\n");
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn test_formatter_keeps_comments() {
+        let source = "// header\n\nvar a = 1; // trailing\n{\n  // inside\n  print a;\n  // last\n}\n// footer\n";
+        let expected = "// header\n\nvar a = 1; // trailing\n{\n    // inside\n    print a;\n    // last\n}\n// footer\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_formatter_wraps_long_calls() {
+        let source = "someFunction(firstArgumentName, secondArgumentName, thirdArgumentName, fourthOne);";
+        let expected = "someFunction(\n    firstArgumentName,\n    secondArgumentName,\n    thirdArgumentName,\n    fourthOne\n);\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_formatter_lambdas() {
+        assert_eq!(format("var f=(a,b)=>a+b;var g=()=>nil;"), "var f = (a, b) => a + b;\nvar g = () => nil;\n");
+        // a block body as the last argument stays on the call's line
+        let source = "map(xs,fun(x){\n// twice\nreturn x*2;});";
+        let expected = "map(xs, fun (x) {\n    // twice\n    return x * 2;\n});\n";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_formatter_idempotent() {
+        let source = "var a=1;\n\n\n// c\nwhile(a<10){if(a==2)print a;else if(a==3){print \"three\";}a+=a%2?1:2**2;}\nfor(;;){}\ntry{throw a;}catch(e){\n// ignored\n}finally{a=0;}\n";
+        let once = format(source);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn test_formatter_refuses_broken_source() {
+        assert!(matches!(format_source("print (1;"), Err(FormatError::Parse(errors)) if errors.len() == 1));
+        assert!(matches!(format_source("print 1 @ 2;"), Err(FormatError::Scan(_))));
+        // comments that would have to move
+        assert!(matches!(format_source("var m = {\"a\": 1, // one\n \"b\": 2};"), Err(FormatError::Comment(1))));
+        assert!(matches!(format_source("fun f(a,\n // explain b\n b) {}"), Err(FormatError::Comment(2))));
+        assert!(matches!(format_source("map(xs, // each\n fun () {\n return 1; });"), Err(FormatError::Comment(1))));
+        assert!(matches!(format_source("if (a) {\n} // then\nelse {\n print 1;\n}"), Err(FormatError::Comment(2))));
+    }
+
+    #[test]
+    fn test_formatter_one_line_blocks_keep_trailing_comments() {
+        let cases = [
+            ("while (true) {} // c", "while (true) {} // c\n"),
+            ("fun f() {} // c", "fun f() {} // c\n"),
+            ("if (x) {} // c", "if (x) {} // c\n"),
+            ("{} // c", "{} // c\n"),
+            ("var f = fun () {}; // c", "var f = fun () {}; // c\n"),
+            ("try { x; } catch (e) {} // c", "try {\n    x;\n} catch (e) {} // c\n"),
+            ("print - -1;", "print - -1;\n"),
+        ];
+        for (source, expected) in cases {
+            let once = format(source);
+            assert_eq!(once, expected);
+            assert_eq!(format(&once), once);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::HashSet, mem::size_of, sync::{Arc, Mutex}};
+
+use crate::token_type::Value;
+
+use super::{environment::Environment, map::{LoxMap, MapKey}};
+
+// Rough sizes of what a script keeps on the heap, for Interp's memory limit
+// (see Interp::set_memory_limit). Good enough to tell a script using a few
+// MB from one heading for gigabytes; allocator overhead, spare capacity and
+// the syntax tree aren't counted.
+
+// a list or map's Arc<Mutex<..>> with nothing in it yet
+pub const COLLECTION_BYTES: usize = 64;
+// an Environment with no variables
+pub const SCOPE_BYTES: usize = 64;
+// a Lox function, mostly the copy of its declaration it keeps
+pub const FUNCTION_BYTES: usize = 256;
+
+pub fn string_size(len: usize) -> usize {
+    size_of::<Value>().saturating_add(len)
+}
+
+// What cloning the value allocates: strings are copied, where lists, maps
+// and functions are only shared.
+pub fn clone_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => string_size(s.len()),
+        _ => size_of::<Value>(),
+    }
+}
+
+pub fn list_size(items: &[Value]) -> usize {
+    COLLECTION_BYTES + items.iter().map(clone_size).sum::<usize>()
+}
+
+pub fn map_entry_size(key: &MapKey, value: &Value) -> usize {
+    // LoxMap keeps each key twice, in its entries and its positions
+    let key_size = size_of::<MapKey>() + match key {
+        MapKey::String(s) => s.len(),
+        _ => 0,
+    };
+    2 * key_size + size_of::<usize>() + clone_size(value)
+}
+
+pub fn map_size(map: &LoxMap) -> usize {
+    COLLECTION_BYTES + map.iter().map(|(key, value)| map_entry_size(key, value)).sum::<usize>()
+}
+
+pub fn binding_size(name: &str, value: &Value) -> usize {
+    size_of::<String>() + name.len() + clone_size(value)
+}
+
+// Something shared that's been found but not counted yet.
+enum Shared {
+    Scope(Arc<Mutex<Environment>>),
+    List(Arc<Mutex<Vec<Value>>>),
+    Map(Arc<Mutex<LoxMap>>),
+}
+
+// Everything reachable from the given scopes and values, counting each list,
+// map, function and scope once however many references there are to it
+// (they can form cycles). A worklist rather than recursion, since lists can nest
+// as deeply as a script likes. Anything locked right now is skipped rather
+// than waited for, it's us holding it.
+pub fn reachable_size(roots: &[&Arc<Mutex<Environment>>], values: &[Value]) -> usize {
+    let mut seen: HashSet<usize> = HashSet::new();
+    let mut work: Vec<Shared> = Vec::new();
+    for root in roots {
+        if seen.insert(Arc::as_ptr(root) as usize) {
+            work.push(Shared::Scope(Arc::clone(root)));
+        }
+    }
+
+    let mut total = 0;
+    for value in values {
+        total += clone_size(value) + found(value, &mut seen, &mut work);
+    }
+    while let Some(shared) = work.pop() {
+        match shared {
+            Shared::Scope(scope) => {
+                let Ok(scope) = scope.try_lock() else { continue };
+                total += SCOPE_BYTES;
+                for (name, value) in scope.values() {
+                    total += binding_size(name, value) + found(value, &mut seen, &mut work);
+                }
+                if let Some(enclosing) = scope.enclosing() {
+                    if seen.insert(Arc::as_ptr(enclosing) as usize) {
+                        work.push(Shared::Scope(Arc::clone(enclosing)));
+                    }
+                }
+            }
+            Shared::List(list) => {
+                let Ok(items) = list.try_lock() else { continue };
+                total += list_size(&items);
+                for item in items.iter() {
+                    total += found(item, &mut seen, &mut work);
+                }
+            }
+            Shared::Map(map) => {
+                let Ok(map) = map.try_lock() else { continue };
+                total += map_size(&map);
+                for (_, value) in map.iter() {
+                    total += found(value, &mut seen, &mut work);
+                }
+            }
+        }
+    }
+    total
+}
+
+// Queues up whatever `value` shares that hasn't been seen before. Returns
+// what it costs on top of the value itself, which is only ever a function.
+fn found(value: &Value, seen: &mut HashSet<usize>, work: &mut Vec<Shared>) -> usize {
+    match value {
+        Value::List(list) if seen.insert(Arc::as_ptr(list) as usize) => work.push(Shared::List(Arc::clone(list))),
+        Value::Map(map) if seen.insert(Arc::as_ptr(map) as usize) => work.push(Shared::Map(Arc::clone(map))),
+        Value::Callable(function) if !function.is_native() && seen.insert(Arc::as_ptr(function) as *const () as usize) => {
+            if let Some(closure) = function.closure() {
+                if seen.insert(Arc::as_ptr(&closure) as usize) {
+                    work.push(Shared::Scope(closure));
+                }
+            }
+            return FUNCTION_BYTES;
+        }
+        _ => {}
+    }
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::lox::ast::environment::Environment;
+    use crate::token_type::Value;
+
+    use super::{reachable_size, COLLECTION_BYTES, SCOPE_BYTES};
+
+    #[test]
+    fn test_reachable_size() {
+        let scope = Arc::new(Mutex::new(Environment::new(None)));
+        let empty = reachable_size(&[&scope], &[]);
+        assert_eq!(empty, SCOPE_BYTES);
+
+        scope.lock().unwrap().define(&"s".to_string(), &Value::String("x".repeat(1000)));
+        let with_string = reachable_size(&[&scope], &[]);
+        assert!(with_string > empty + 1000);
+
+        // a list that holds itself, twice over, is counted once
+        let list = Arc::new(Mutex::new(Vec::new()));
+        list.lock().unwrap().push(Value::List(Arc::clone(&list)));
+        scope.lock().unwrap().define(&"a".to_string(), &Value::List(Arc::clone(&list)));
+        scope.lock().unwrap().define(&"b".to_string(), &Value::List(Arc::clone(&list)));
+        let with_list = reachable_size(&[&scope, &scope], &[]);
+        assert!(with_list > with_string + COLLECTION_BYTES && with_list < with_string + COLLECTION_BYTES + 200);
+
+        // every level of nesting is followed
+        let mut nested = Value::Nil;
+        for _ in 0..1000 {
+            nested = Value::List(Arc::new(Mutex::new(vec![nested])));
+        }
+        let inner = Arc::new(Mutex::new(Environment::new(Some(Arc::clone(&scope)))));
+        inner.lock().unwrap().define(&"nested".to_string(), &nested);
+        assert!(reachable_size(&[&inner], &[]) > with_list + 1000 * COLLECTION_BYTES);
+
+        // values held outside any scope count too, with what they share
+        let held = [Value::String("y".repeat(1000)), Value::List(Arc::clone(&list))];
+        assert!(reachable_size(&[&inner], &held) > reachable_size(&[&inner], &[]) + 1000);
+        assert!(reachable_size(&[], &held) > 1000 + COLLECTION_BYTES);
+
+        // something locked is skipped rather than deadlocked on
+        let _held = list.lock().unwrap();
+        assert!(reachable_size(&[&scope], &[]) < with_list);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::TokenType;
 
-use super::{environment::Environment, Binary, Clock, Expr, ExprVisitor, Grouping, Literal, Logical, LoxCallable, NullFunc, Stmt, StmtVisitor, Token, Unary, Value, Variable};
-use std::{any::{Any, TypeId}, cell::RefCell, fmt, rc::Rc, sync::Arc, sync::Mutex};
+use super::{environment::Environment, heap, stack, map::{LoxMap, MapKey}, natives::{self, Permissions, Rng, SystemClock, TimeSource}, printer::Printer, walk_expr, walk_statement, Binary, LoxCallable, Conditional, Expr, ExprVisitor, FuncStmt, Grouping, Index, IndexAssign, Lambda, ListExpr, Literal, Logical, LoopJump, MapExpr, LoxFunction, ReturnStmt, Stmt, StmtVisitor, ThrowStmt, Token, TryStmt, Unary, Value, Variable};
+use std::{any::{Any, TypeId}, cell::RefCell, fmt, io::{self, BufRead}, rc::Rc, sync::Arc, sync::Mutex, time::Duration};
 
 // use main::runtime_error func
 use crate::runtime_error;
@@ -10,38 +10,401 @@ use crate::runtime_error;
 
 pub struct Interp {
     environment: Arc<Mutex<Environment>>,
-    globals: Arc<Mutex<Environment>>
+    globals: Arc<Mutex<Environment>>,
+    // print each simple statement to stderr before running it (--trace)
+    trace: bool,
+    // what the I/O natives are allowed to touch, up to whoever embeds us
+    permissions: Permissions,
+    // where readLine() reads from
+    input: Box<dyn BufRead + Send>,
+    // what args() gives the script
+    script_args: Vec<String>,
+    // what clock(), now() and sleep() use
+    time: Box<dyn TimeSource>,
+    // behind random(), randomInt() and shuffle()
+    rng: Rng,
+    // the calls in progress, outermost first
+    frames: Vec<Frame>,
+    // how many of them there can be before a call is a "Stack overflow"
+    max_call_depth: usize,
+    // statements and expressions run so far, see step()
+    steps: u64,
+    // how many more steps there's fuel for, None for no limit
+    fuel: Option<u64>,
+    // asked for more once the fuel runs out, 0 means stop
+    refuel: Option<Box<dyn FnMut() -> u64 + Send>>,
+    // when to stop, in the time source's milliseconds
+    deadline: Option<f64>,
+    // the line of the last step that had a token, for limit errors
+    line: usize,
+    // roughly how many bytes the script's values and scopes can take up,
+    // None for no limit
+    memory_limit: Option<usize>,
+    // what was reachable at the last count, and what's been charged since
+    heap_live: usize,
+    heap_charged: usize,
+    // the scopes execute_block has put aside to run a block or a call. A
+    // caller's scope can't be reached from the one its callee runs in.
+    suspended: Vec<Arc<Mutex<Environment>>>,
+    // values made on the way to something bigger that isn't anywhere yet,
+    // e.g. a list literal's elements, see collect_held()
+    held: Vec<Value>,
+}
+
+// With less stack than 1000 calls take (see stack.rs), running low on it is
+// what stops a deep recursion first. That's a "Stack overflow" too.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// Reading the clock every step would cost more than the step itself.
+const STEPS_PER_DEADLINE_CHECK: u64 = 256;
+
+// One call in progress: the function's name and the ')' of the call that
+// started it, for the line it was called from.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    pub call_site: Token,
 }
 
 impl Interp {
     pub fn new() -> Self {
-        let globals = Environment::new(None);
-        // globals.define(
-        //     &"clock".to_string(),
-        //     Clock
-        // );
+        let (environment, globals) = Self::fresh_environment();
         Self {
-            environment: Arc::new(Mutex::new(Environment::new(None))),
-            globals: Arc::new(Mutex::new(globals))
+            environment,
+            globals,
+            trace: false,
+            permissions: Permissions::all(),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            script_args: Vec::new(),
+            time: Box::new(SystemClock),
+            rng: Rng::from_time(),
+            frames: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            steps: 0,
+            fuel: None,
+            refuel: None,
+            deadline: None,
+            line: 0,
+            memory_limit: None,
+            heap_live: 0,
+            heap_charged: 0,
+            suspended: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    // the natives live in globals, the program's own top level is a
+    // scope inside that, so it can shadow them
+    fn fresh_environment() -> (Arc<Mutex<Environment>>, Arc<Mutex<Environment>>) {
+        let mut globals = Environment::new(None);
+        natives::define_natives(&mut globals);
+        let globals = Arc::new(Mutex::new(globals));
+        (Arc::new(Mutex::new(Environment::new(Some(Arc::clone(&globals))))), globals)
+    }
+
+    // forget everything the program defined, but keep the settings below
+    pub fn reset(&mut self) {
+        (self.environment, self.globals) = Self::fresh_environment();
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    pub fn set_input(&mut self, input: Box<dyn BufRead + Send>) {
+        self.input = input;
+    }
+
+    // the next line from the input without its line ending, None at the end
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
         }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.script_args = args;
+    }
+
+    pub fn args(&self) -> &Vec<String> {
+        &self.script_args
+    }
+
+    // for embedders and tests, jlox always runs on the SystemClock
+    #[allow(dead_code)]
+    pub fn set_time_source(&mut self, time: Box<dyn TimeSource>) {
+        self.time = time;
+    }
+
+    pub fn time_source(&mut self) -> &mut dyn TimeSource {
+        self.time.as_mut()
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn frames(&self) -> &Vec<Frame> {
+        &self.frames
+    }
+
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // For running code that can't be trusted to finish: every statement and
+    // expression run is a step, and once `fuel` of them have run the script
+    // stops with "Out of fuel". None takes the limit off.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    // fuel, add_fuel, set_refuel and steps are for embedding the interpreter;
+    // jlox itself only ever sets a fresh limit (see repl::EntryLimits).
+
+    // what's left, None if there's no limit
+    #[allow(dead_code)]
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // Top up the fuel, e.g. before running more code after an "Out of fuel".
+    // Does nothing without a limit.
+    #[allow(dead_code)]
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    // Called when the fuel runs out, mid-statement, for how much more to
+    // carry on with; 0 stops the script. A scheduler taking turns between
+    // scripts can wait in here for this one's next turn and hand out its
+    // next slice of steps.
+    #[allow(dead_code)]
+    pub fn set_refuel(&mut self, refuel: Option<Box<dyn FnMut() -> u64 + Send>>) {
+        self.refuel = refuel;
+    }
+
+    #[allow(dead_code)]
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Stop with "Timed out" once `timeout` has gone by from now, by the time
+    // source's clock. None takes the limit off.
+    pub fn set_deadline(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| self.time.now_ms() + timeout.as_secs_f64() * 1000.0);
+    }
+
+    // how long until the deadline, None if there isn't one
+    pub fn time_left_ms(&self) -> Option<f64> {
+        self.deadline.map(|deadline| (deadline - self.time.now_ms()).max(0.0))
+    }
+
+    // Caps roughly how much memory the script's strings, lists, maps,
+    // functions and scopes can take up; going over stops it with "Out of
+    // memory". None takes the limit off.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.memory_limit = bytes;
+        self.heap_live = self.heap_size();
+        self.heap_charged = 0;
+    }
+
+    // everything the program can still get at, counted now (see heap.rs)
+    pub fn heap_size(&self) -> usize {
+        let mut roots = vec![&self.environment, &self.globals];
+        roots.extend(&self.suspended);
+        heap::reachable_size(&roots, &self.held)
+    }
+
+    // Runs `build`, which passes each value it makes to hold(), and gives
+    // back all of them in order. Until then they're counted as reachable,
+    // so a list built up a value at a time runs into the memory limit as it
+    // grows rather than only once it's finished.
+    pub fn collect_held(&mut self, build: impl FnOnce(&mut Self) -> Result<()>) -> Result<Vec<Value>> {
+        let mark = self.held.len();
+        let result = build(self);
+        let values = self.held.split_off(mark);
+        result.map(|()| values)
+    }
+
+    pub fn hold(&mut self, value: Value) {
+        self.held.push(value);
+    }
+
+    // Called before anything that takes up about `bytes` more memory. Cheap
+    // until what's been charged since the last count could add up to more
+    // than the limit, then what's reachable is counted again and that
+    // decides; over-charging only means counting more often. Close to the
+    // limit it's recounted after a 16th of it at the least, so a script can
+    // go over by that much before it's stopped.
+    pub fn charge(&mut self, bytes: usize, token: Option<&Token>) -> Result<()> {
+        let Some(limit) = self.memory_limit else { return Ok(()) };
+        self.heap_charged = self.heap_charged.saturating_add(bytes);
+        let recount_at = limit.max(self.heap_live.saturating_add(limit / 16));
+        if self.heap_live.saturating_add(self.heap_charged) <= recount_at {
+            return Ok(());
+        }
+        self.heap_live = self.heap_size();
+        self.heap_charged = bytes;
+        if self.heap_live.saturating_add(bytes) > limit {
+            return Err(self.limit_error(token, Limit::Memory));
+        }
+        Ok(())
+    }
+
+    // Charged for every statement and expression, before it runs. Without
+    // any limits, and with stack to spare, that's all there is to it; this
+    // is the hot path.
+    #[inline]
+    fn step<'a>(&mut self, node_token: impl FnOnce() -> Option<&'a Token>) -> Result<()> {
+        self.steps += 1;
+        let limited = self.fuel.is_some() || self.deadline.is_some() || self.memory_limit.is_some();
+        if !limited && !stack::running_low() {
+            return Ok(());
+        }
+        self.check_limits(node_token())
+    }
+
+    // `token` is what a limit error points at; nodes without one (literals,
+    // blocks) get the line of the last step that had one.
+    #[inline(never)]
+    fn check_limits(&mut self, token: Option<&Token>) -> Result<()> {
+        if let Some(token) = token {
+            self.line = token.line;
+            // an ordinary, catchable error like a call too many. A node
+            // without a token leaves it to the next one, never far off.
+            if stack::running_low() {
+                return Err(RuntimeError::new(token.clone(), "Stack overflow".to_string()));
+            }
+        }
+        if self.fuel == Some(0) {
+            let more = self.refuel.as_mut().map_or(0, |refuel| refuel());
+            if more == 0 {
+                return Err(self.limit_error(token, Limit::Fuel));
+            }
+            self.fuel = Some(more);
+        }
+        if let Some(fuel) = &mut self.fuel {
+            *fuel -= 1;
+        }
+        if self.steps.is_multiple_of(STEPS_PER_DEADLINE_CHECK) {
+            self.check_deadline(token)?;
+        }
+        Ok(())
+    }
+
+    // also used by sleep(), which doesn't sleep past the deadline
+    pub fn check_deadline(&mut self, token: Option<&Token>) -> Result<()> {
+        match self.time_left_ms() {
+            Some(left) if left <= 0.0 => Err(self.limit_error(token, Limit::Deadline)),
+            _ => Ok(()),
+        }
+    }
+
+    fn limit_error(&self, token: Option<&Token>, limit: Limit) -> RuntimeError {
+        let token = token.cloned().unwrap_or_else(|| Token::new(TokenType::EOF, String::new(), Literal::Nil, self.line));
+        let mut error = RuntimeError::new(token, limit.message().to_string());
+        error.limit = Some(limit);
+        error
+    }
+
+    // Every call goes through here, from Lox code or from a native calling
+    // back into it, so the frames are right whoever makes the call.
+    pub fn call_function(&mut self, function: &Arc<dyn LoxCallable>, paren: &Token, arguments: Vec<Value>) -> Result<Value> {
+        // an ordinary, catchable error, raised in the caller's frame
+        if self.frames.len() >= self.max_call_depth {
+            return Err(RuntimeError::new(paren.clone(), "Stack overflow".to_string()));
+        }
+        self.frames.push(Frame { function: function.name(), call_site: paren.clone() });
+        let result = function.call(self, paren, arguments).map_err(|mut err| {
+            // the first call an error gets out of is the one it was raised
+            // in, and all the frames around it are still here to copy
+            if err.stack.is_empty() {
+                err.stack = self.frames.iter().rev().cloned().collect();
+            }
+            err
+        });
+        self.frames.pop();
+        result
+    }
+}
+
+// Numbers print without a trailing ".0", as inf / -inf / nan when they're
+// not finite, and with an exponent (1e+21, 1.5e-7) when very big or small,
+// rather than as hundreds of digits.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "nan".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if n != 0.0 && (n.abs() >= 1e21 || n.abs() < 1e-6) {
+        let s = format!("{n:e}");
+        let (mantissa, exponent) = s.split_once('e').unwrap();
+        if exponent.starts_with('-') {
+            format!("{mantissa}e{exponent}")
+        } else {
+            format!("{mantissa}e+{exponent}")
+        }
+    } else {
+        // Display already leaves off the ".0"
+        format!("{n}")
     }
 }
 
 pub fn stringify(val: &Value) -> String {
     match val {
-        Value::Number(s) => {
-            let num_string = format!("{s}");
-            // if val_string ends with ".0" then remove the ".0"
-            if num_string.ends_with(".0") {
-                // snip off last 2 characters
-                num_string[..num_string.len()-2].to_string()
-            } else {
-                num_string
-            }
-        }
+        Value::Number(n) => format_number(*n),
         Value::String(s) => format!("\"{s}\""),
         Value::Boolean(b) => b.to_string(),
         Value::Nil => "nil".to_string(),
+        Value::List(list) => {
+            // a list that contains itself is already locked further up
+            let Ok(items) = list.try_lock() else { return "[...]".to_string() };
+            let items: Vec<String> = items.iter().map(stringify).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Map(map) => {
+            let Ok(map) = map.try_lock() else { return "{...}".to_string() };
+            let entries: Vec<String> = map.iter()
+                .map(|(key, value)| format!("{}: {}", stringify(&key.to_value()), stringify(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Callable(callable) if callable.is_native() => format!("<native fn {}>", callable.name()),
+        Value::Callable(callable) => format!("<fn {}>", callable.name()),
+        Value::Range(start, end) => format!("range({}, {})", format_number(*start), format_number(*end)),
+    }
+}
+
+// The name of a value's type, for error messages.
+pub fn type_name(val: &Value) -> &'static str {
+    match val {
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Boolean(_) => "boolean",
+        Value::Nil => "nil",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Callable(_) => "function",
+        Value::Range(..) => "range",
     }
 }
 
@@ -53,7 +416,7 @@ impl Interp {
     pub fn interpret(&mut self, expr: &Expr) {
         let out = self.visit_expr(expr);
         if let Ok(val) = &out {
-            let val_string: String = stringify(&val);
+            let val_string: String = stringify(val);
             println!("{val_string}");
 
         } else {
@@ -68,18 +431,34 @@ impl Interp {
         // visit each stmt one by one, evaluating. If any raise RuntimeError,
         // we return error. o/w finally return Ok(())
         for stmt in stmts {
-            let res = self.visit_statement(stmt).unwrap_or_else(
-                |error| {
-                    runtime_error(error);
+            // the resolver doesn't let `return` get out of a function
+            if let Err(Interrupt::Error(error)) = self.visit_statement(stmt) {
+                // the rest would run straight into the same limit
+                let limit = error.limit.is_some();
+                runtime_error(error);
+                if limit {
+                    break;
                 }
-            );
+            }
         }
         Ok(())
     }
 
-    pub fn execute_block(&mut self, statements: &Vec<Stmt>, mut environment: Arc<Mutex<Environment>>) -> Result<()>{
-        std::mem::swap(&mut self.environment, &mut environment);
-        
+    // what the program has defined, as seen from the current scope (for the
+    // REPL's :env). The natives in globals are left out.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        self.environment.lock().unwrap().bindings(Some(&self.globals))
+    }
+
+    pub fn native_names(&self) -> Vec<String> {
+        self.globals.lock().unwrap().bindings(None).into_iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn execute_block(&mut self, statements: &Vec<Stmt>, environment: Arc<Mutex<Environment>>) -> Exec {
+        self.charge(heap::SCOPE_BYTES, None)?;
+        let previous = std::mem::replace(&mut self.environment, environment);
+        self.suspended.push(previous);
+
         // Execute block with new environment
         let result = (|| {
             for stmt in statements {    
@@ -88,9 +467,94 @@ impl Interp {
             Ok(())
         })();
 
-        std::mem::swap(&mut self.environment, &mut environment);
+        self.environment = self.suspended.pop().unwrap();
         result
     }
+
+    // A call or index in a chain like `a?.[i](x)[j]`, None if a `?.` in it
+    // found nil. That skips the rest of the chain, just as if there were a
+    // `?.` in front of every link after it. A nil that a link without one
+    // gives back is still an error to index or call.
+    fn call_link(&mut self, call: &super::Call) -> Result<Option<Value>> {
+        let Some(callee) = self.chain_object(&call.callee)? else { return Ok(None) };
+        self.finish_call(call, callee)
+    }
+
+    fn index_link(&mut self, index: &Index) -> Result<Option<Value>> {
+        let Some(object) = self.chain_object(&index.object)? else { return Ok(None) };
+        if index.optional.is_some() && matches!(object, Value::Nil) {
+            return Ok(None);
+        }
+        let i = self.visit_expr(&index.index)?;
+        index_get(&index.bracket, &object, &i).map(Some)
+    }
+
+    // what a link is called on or indexes. A chain doesn't reach into
+    // brackets, `(a?.[i])[j]` is an error if a is nil.
+    fn chain_object(&mut self, object: &Expr) -> Result<Option<Value>> {
+        match object {
+            Expr::Call(call) => {
+                self.step(|| Some(&call.paren))?;
+                self.call_link(call)
+            }
+            Expr::Index(index) => {
+                self.step(|| Some(&index.bracket))?;
+                self.index_link(index)
+            }
+            _ => self.visit_expr(object).map(Some),
+        }
+    }
+
+    // the rest of a call once its callee has been evaluated
+    fn finish_call(&mut self, call: &super::Call, callee: Value) -> Result<Option<Value>> {
+        // f?.(x) doesn't evaluate its arguments when there's nothing to call
+        if call.optional.is_some() && matches!(callee, Value::Nil) {
+            return Ok(None);
+        }
+
+        let args = self.collect_held(|interp| {
+            for arg in &call.arguments {
+                let arg = interp.visit_expr(arg)?;
+                interp.hold(arg);
+            }
+            Ok(())
+        })?;
+
+        let Value::Callable(func) = callee else {
+            return Err(RuntimeError::new(call.paren.clone(), format!(
+                "can only call functions, not a {}", type_name(&callee))
+            ));
+        };
+
+        if func.arity() != args.len() {
+            return Err(RuntimeError::new(call.paren.clone(), format!(
+                "Arity mismatch: func wants: {}, # args given: {}", func.arity(), args.len())
+            ));
+        }
+
+        self.call_function(&func, &call.paren, args).map(Some)
+    }
+
+    // What a for-in walks over. A range is counted out as the loop goes, it
+    // could be far bigger than anything else in the program; anything else
+    // is taken up front by iteration_items.
+    fn for_in_items(&mut self, for_in: &super::ForInStmt) -> Result<Items> {
+        let iterable = self.evaluate(&for_in.iterable)?;
+        if let Value::Range(start, end) = iterable {
+            return Ok(Items::Range(start, end));
+        }
+        let items = iteration_items(&for_in.keyword, &iterable)?;
+        self.charge(heap::list_size(&items), Some(&for_in.keyword))?;
+        Ok(Items::Values(items.into_iter()))
+    }
+
+    // binary_op, with string concatenation charged for first
+    fn binary(&mut self, operator: &Token, l: Value, r: Value) -> Result<Value> {
+        if let (TokenType::PLUS, Value::String(a), Value::String(b)) = (operator.token_type, &l, &r) {
+            self.charge(heap::string_size(a.len() + b.len()), Some(operator))?;
+        }
+        binary_op(operator, l, r)
+    }
 }
 
 
@@ -104,21 +568,50 @@ impl Interp {
 
 
 
-impl StmtVisitor<Result<()>> for Interp {
+// Why a statement stopped early: a runtime error, a `return` on its way
+// out to the function call, or a break / continue on its way out to a loop
+// (the loop's label, if it named one, is in the LoopJump, boxed like
+// RuntimeError's details). Expressions can only fail with a RuntimeError.
+#[derive(Debug)]
+pub enum Interrupt {
+    Error(RuntimeError),
+    Return(Value),
+    Break(Box<LoopJump>),
+    Continue(Box<LoopJump>),
+}
+
+impl From<RuntimeError> for Interrupt {
+    fn from(error: RuntimeError) -> Self {
+        Interrupt::Error(error)
+    }
+}
+
+pub type Exec = std::result::Result<(), Interrupt>;
+
+impl StmtVisitor<Exec> for Interp {
+    fn visit_statement(&mut self, stmt: &Stmt) -> Exec {
+        // blocks, loops etc. would print their whole body, so just the
+        // statements inside them get traced
+        if self.trace && matches!(stmt, Stmt::Expression(_) | Stmt::Print(_) | Stmt::Var(_)) {
+            eprintln!("trace: {}", Printer.print_stmt(stmt));
+        }
+        self.step(|| stmt_token(stmt))?;
+        walk_statement(self, stmt)
+    }
 
-    fn visit_expr_statement(&mut self, expr: &Expr) -> Result<()> {
+    fn visit_expr_statement(&mut self, expr: &Expr) -> Exec {
         let val = self.visit_expr(expr)?;
         return Ok(());
     }
 
-    fn visit_print_statement(&mut self, expr: &Expr) -> Result<()> {
+    fn visit_print_statement(&mut self, expr: &Expr) -> Exec {
         let val = self.visit_expr(expr)?;
         println!("{}", stringify(&val));
         return Ok(());
         // Err(RuntimeError::new(Token::new(TokenType::NIL, "".to_string(), Literal::Nil, 0), "Expected print statement".to_string()))
     }
 
-    fn visit_if_statement(&mut self, if_stmt: &super::IfStmt) -> Result<()> {
+    fn visit_if_statement(&mut self, if_stmt: &super::IfStmt) -> Exec {
         if is_truthy(&self.evaluate(&if_stmt.condition)?) {
             self.visit_statement(&if_stmt.if_branch)
         } else if let Some(else_stmt) = &if_stmt.else_branch {
@@ -128,18 +621,111 @@ impl StmtVisitor<Result<()>> for Interp {
         }
     }
 
-    fn visit_while_statement(&mut self, while_stmt: &super::WhileStmt) -> Result<()> {
+    fn visit_while_statement(&mut self, while_stmt: &super::WhileStmt) -> Exec {
         while (is_truthy(&self.evaluate(&while_stmt.condition)?)) {
-            self.visit_statement(&while_stmt.body)?
+            if !keep_looping(&while_stmt.label, self.visit_statement(&while_stmt.body))? {
+                break;
+            }
         }
         Ok(())
     }
 
-    fn visit_func_statement(&mut self, func_stmt: &super::FuncStmt) -> Result<()> {
+    fn visit_for_statement(&mut self, for_stmt: &super::ForStmt) -> Exec {
+        // the initializer's variable lives in its own scope around the loop,
+        // exactly as if we'd desugared to { init; while (cond) { body; inc; } }
+        self.charge(heap::SCOPE_BYTES, Some(&for_stmt.keyword))?;
+        let loop_env = Arc::new(Mutex::new(Environment::new(Some(Arc::clone(&self.environment)))));
+        let mut previous = loop_env;
+        std::mem::swap(&mut self.environment, &mut previous);
+
+        let result = (|| {
+            if let Some(initializer) = &for_stmt.initializer {
+                self.visit_statement(initializer)?;
+            }
+            loop {
+                if let Some(condition) = &for_stmt.condition {
+                    if !is_truthy(&self.evaluate(condition)?) {
+                        break;
+                    }
+                }
+                // a continue still runs the increment
+                if !keep_looping(&for_stmt.label, self.visit_statement(&for_stmt.body))? {
+                    break;
+                }
+                if let Some(increment) = &for_stmt.increment {
+                    self.evaluate(increment)?;
+                }
+            }
+            Ok(())
+        })();
+
+        std::mem::swap(&mut self.environment, &mut previous);
+        result
+    }
+
+    fn visit_for_in_statement(&mut self, for_in: &super::ForInStmt) -> Exec {
+        for item in self.for_in_items(for_in)? {
+            // a new scope each time round, so a closure made in the body
+            // keeps the value it saw rather than the last one
+            self.charge(heap::SCOPE_BYTES + heap::binding_size(&for_in.name.lexeme, &item), Some(&for_in.keyword))?;
+            let mut scope = Arc::new(Mutex::new(Environment::new(Some(Arc::clone(&self.environment)))));
+            scope.lock().unwrap().define(&for_in.name.lexeme, &item);
+            std::mem::swap(&mut self.environment, &mut scope);
+            let result = self.visit_statement(&for_in.body);
+            std::mem::swap(&mut self.environment, &mut scope);
+            if !keep_looping(&for_in.label, result)? {
+                break;
+            }
+        }
         Ok(())
     }
 
-    fn visit_block_statement(&mut self, statements: &Vec<Stmt>) -> Result<()> {
+    fn visit_func_statement(&mut self, func_stmt: &super::FuncStmt) -> Exec {
+        self.charge(heap::FUNCTION_BYTES, Some(&func_stmt.name))?;
+        let function = LoxFunction::new(func_stmt.clone(), Arc::clone(&self.environment));
+        self.environment.lock().unwrap().define(&func_stmt.name.lexeme, &Value::Callable(Arc::new(function)));
+        Ok(())
+    }
+
+    fn visit_return_statement(&mut self, return_stmt: &ReturnStmt) -> Exec {
+        let value = self.evaluate(&return_stmt.value)?;
+        Err(Interrupt::Return(value))
+    }
+
+    fn visit_break_statement(&mut self, jump: &LoopJump) -> Exec {
+        Err(Interrupt::Break(Box::new(jump.clone())))
+    }
+
+    fn visit_continue_statement(&mut self, jump: &LoopJump) -> Exec {
+        Err(Interrupt::Continue(Box::new(jump.clone())))
+    }
+
+    fn visit_throw_statement(&mut self, throw: &ThrowStmt) -> Exec {
+        let value = self.visit_expr(&throw.value)?;
+        Err(Interrupt::Error(RuntimeError::thrown(throw.keyword.clone(), value)))
+    }
+
+    // Only errors are caught. A return, break or continue goes straight
+    // through, after the finally.
+    fn visit_try_statement(&mut self, try_stmt: &TryStmt) -> Exec {
+        let mut result = self.visit_block_statement(&try_stmt.body);
+        if let (Err(Interrupt::Error(error)), Some(catch)) = (&result, &try_stmt.catch) {
+            // running out of fuel, time or memory isn't the script's to handle
+            if error.limit.is_none() {
+                let mut environment = Environment::new(Some(Arc::clone(&self.environment)));
+                environment.define(&catch.name.lexeme, &error.to_value());
+                result = self.execute_block(&catch.body, Arc::new(Mutex::new(environment)));
+            }
+        }
+        // runs however the try / catch ended; if it stops early itself,
+        // that's what carries on out instead
+        if let Some(finally) = &try_stmt.finally {
+            self.visit_block_statement(finally)?;
+        }
+        result
+    }
+
+    fn visit_block_statement(&mut self, statements: &Vec<Stmt>) -> Exec {
         // Create new environment with current environment as enclosing
         let block_env = Arc::new(Mutex::new(Environment::new(Some(Arc::clone(&self.environment)))));
         
@@ -163,29 +749,56 @@ impl StmtVisitor<Result<()>> for Interp {
         result
     }
 
-    fn visit_var_statement(&mut self, var: &Variable) -> Result<()> {
+    fn visit_var_statement(&mut self, var: &Variable) -> Exec {
         // at this point we surely need to save the value in the environment
         // NB if var x; (without definition), we actually set .initializer
         // to Expr::Null in parser.
         let val = self.visit_expr(&var.initializer)?;
+        self.charge(heap::binding_size(&var.name.lexeme, &val), Some(&var.name))?;
         self.environment.lock().unwrap().define(&var.name.lexeme, &val);
-        return Ok(());
+        Ok(())
     }
 }
 
 
 //NB: I don't think with this matching system, we need to "check number operand"
 impl ExprVisitor<Result<Value>> for Interp {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<Value> {
+        self.step(|| expr_token(expr))?;
+        walk_expr(self, expr)
+    }
+
     fn visit_assignment(&mut self, assignment: &super::Assign) -> Result<Value> {
-        let value = self.visit_expr(&assignment.value)?;
+        let value = match compound_operator(&assignment.operator) {
+            // the variable is read before the right hand side runs, as it
+            // would be for `x = x + value`
+            Some(operator) => {
+                let current = self.environment.lock().unwrap().get(&assignment.name)?;
+                let value = self.visit_expr(&assignment.value)?;
+                self.binary(&operator, current, value)?
+            }
+            None => self.visit_expr(&assignment.value)?,
+        };
+        self.charge(heap::clone_size(&value), Some(&assignment.name))?;
         self.environment.lock().unwrap().assign(&assignment.name, &value)?;
         Ok(value)
     }
 
+    fn visit_conditional(&mut self, conditional: &Conditional) -> Result<Value> {
+        if is_truthy(&self.visit_expr(&conditional.condition)?) {
+            self.visit_expr(&conditional.then_branch)
+        } else {
+            self.visit_expr(&conditional.else_branch)
+        }
+    }
+
     fn visit_logical(&mut self, logical: &Logical) -> Result<Value> {
         let left = self.evaluate(&logical.left)?;
         if logical.operator.token_type == TokenType::OR {
             if is_truthy(&left) {return Ok(left);}
+        } else if logical.operator.token_type == TokenType::QUESTION_QUESTION {
+            // only nil falls through, unlike `or` a false or 0 is kept
+            if !matches!(left, Value::Nil) {return Ok(left);}
         } else {
             if !is_truthy(&left) {return Ok(left);}
         }
@@ -195,40 +808,7 @@ impl ExprVisitor<Result<Value>> for Interp {
     fn visit_binary(&mut self, binary: &Binary) -> Result<Value> {
         let l = self.visit_expr(&binary.left)?;
         let r = self.visit_expr(&binary.right)?;
-
-
-        match binary.operator.token_type {
-            TokenType::EQUAL_EQUAL => Ok(Value::Boolean(is_equal(&l, &r))),
-            TokenType::BANG_EQUAL => Ok(Value::Boolean(!is_equal(&l, &r))),
-            _ => {
-                match (l, r) {
-                    (Value::Number(a), Value::Number(b)) => {
-                    match binary.operator.token_type {
-                        TokenType::GREATER => Ok(Value::Boolean(a > b)),
-                        TokenType::GREATER_EQUAL => Ok(Value::Boolean(a >= b)),
-                        TokenType::LESS => Ok(Value::Boolean(a < b)),
-                        TokenType::LESS_EQUAL => Ok(Value::Boolean(a <= b)),
-                        TokenType::MINUS => Ok(Value::Number(a - b)),
-                        TokenType::SLASH => Ok(Value::Number(a / b)),
-                        TokenType::STAR => Ok(Value::Number(a * b)),
-                        TokenType::PLUS => Ok(Value::Number(a + b)),
-                        _ => Err(RuntimeError::new(binary.operator.clone(), "Unexpected binary operator for numbers".to_string()))    
-                        //panic!("Unexpected binary operator for numbers")
-                    }
-                    }
-                    (Value::String(a), Value::String(b)) => {
-                        if let TokenType::PLUS = binary.operator.token_type {
-                            Ok(Value::String(a.clone() + &b))
-                        } else {
-                            // panic!("Unexpected binary operator for strings")
-                            Err(RuntimeError::new(binary.operator.clone(), "Unexpected binary operator for strings".to_string()))
-                        }
-                    }
-                    // _ => panic!("no more valid operators etc.")
-                    _ => Err(RuntimeError::new(binary.operator.clone(), "no more valid operators etc.".to_string()))
-                }
-            }
-        }
+        self.binary(&binary.operator, l, r)
     }
 
 
@@ -254,23 +834,83 @@ impl ExprVisitor<Result<Value>> for Interp {
     }
 
     fn visit_call(&mut self, call: &super::Call) -> Result<Value> {
-        let callee = self.visit_expr(&call.callee)?;  // get the function
-        // TODO - check if callee is a function
+        Ok(self.call_link(call)?.unwrap_or(Value::Nil))
+    }
 
-        let args: Result<Vec<Value>> = call.arguments.iter().map(|arg| self.visit_expr(arg)).collect();
-        let args = args?;
+    // a new closure each time it's evaluated, over the scope it's in
+    fn visit_lambda(&mut self, lambda: &Lambda) -> Result<Value> {
+        let declaration = FuncStmt {
+            name: Token::new(TokenType::IDENTIFIER, "anonymous".to_string(), Literal::Nil, lambda.keyword.line),
+            parameters: lambda.parameters.clone(),
+            body: lambda.body.clone(),
+        };
+        self.charge(heap::FUNCTION_BYTES, Some(&lambda.keyword))?;
+        Ok(Value::Callable(Arc::new(LoxFunction::new(declaration, Arc::clone(&self.environment)))))
+    }
 
-        // Cast LoxCallable; check that it's indeed callable??
-        let func = NullFunc; // LoxCallable
+    fn visit_list(&mut self, list: &ListExpr) -> Result<Value> {
+        let elements = self.collect_held(|interp| {
+            for element in &list.elements {
+                let element = interp.visit_expr(element)?;
+                interp.hold(element);
+            }
+            Ok(())
+        })?;
+        self.charge(heap::list_size(&elements), Some(&list.bracket))?;
+        Ok(Value::List(Arc::new(Mutex::new(elements))))
+    }
 
-        if func.arity() != args.len() {
-            return Err(RuntimeError::new(call.paren.clone(), format!(
-                "Arity mismatch: func wants: {}, # args given: {}", func.arity(), args.len())
-            ));
+    fn visit_map(&mut self, map: &MapExpr) -> Result<Value> {
+        // keys and values, one after the other
+        let held = self.collect_held(|interp| {
+            for (key, value) in &map.entries {
+                let key = interp.visit_expr(key)?;
+                MapKey::from_value(&key).map_err(|message| RuntimeError::new(map.brace.clone(), message))?;
+                interp.hold(key);
+                let value = interp.visit_expr(value)?;
+                interp.hold(value);
+            }
+            Ok(())
+        })?;
+        let mut entries = LoxMap::new();
+        for pair in held.chunks(2) {
+            entries.insert(MapKey::from_value(&pair[0]).unwrap(), pair[1].clone());
         }
+        self.charge(heap::map_size(&entries), Some(&map.brace))?;
+        Ok(Value::Map(Arc::new(Mutex::new(entries))))
+    }
 
+    fn visit_index(&mut self, index: &Index) -> Result<Value> {
+        Ok(self.index_link(index)?.unwrap_or(Value::Nil))
+    }
 
-        Ok(Value::Nil) // TODO!
+    fn visit_index_assign(&mut self, index_assign: &IndexAssign) -> Result<Value> {
+        let object = self.visit_expr(&index_assign.object)?;
+        let i = self.visit_expr(&index_assign.index)?;
+        let value = match compound_operator(&index_assign.operator) {
+            Some(operator) => {
+                let current = index_get(&index_assign.bracket, &object, &i)?;
+                let value = self.visit_expr(&index_assign.value)?;
+                self.binary(&operator, current, value)?
+            }
+            None => self.visit_expr(&index_assign.value)?,
+        };
+        self.charge(heap::clone_size(&i) + heap::clone_size(&value), Some(&index_assign.bracket))?;
+        match &object {
+            Value::List(list) => {
+                let mut items = list.lock().unwrap();
+                let i = list_index(&index_assign.bracket, &i, items.len())?;
+                items[i] = value.clone();
+            }
+            // adds the key if it's new
+            Value::Map(map) => {
+                let key = map_key(&index_assign.bracket, &i)?;
+                map.lock().unwrap().insert(key, value.clone());
+            }
+            Value::Range(..) => return Err(RuntimeError::new(index_assign.bracket.clone(), "can't assign into a range".to_string())),
+            _ => return Err(not_indexable(&index_assign.bracket, &object)),
+        }
+        Ok(value)
     }
 
     fn visit_grouping(&mut self, grouping: &Grouping) -> Result<Value> {
@@ -300,17 +940,201 @@ impl ExprVisitor<Result<Value>> for Interp {
     fn visit_null(&mut self) -> Result<Value> { Ok(Value::Nil) }
 }
 
+// Whether a loop goes round again once its body has finished with `result`.
+// A break aimed at this loop (no label, or its label) stops it and a continue
+// doesn't; anything else - an error, a return, a jump to an outer loop -
+// carries on out.
+fn keep_looping(label: &Option<Token>, result: Exec) -> std::result::Result<bool, Interrupt> {
+    let ours = |jump: &LoopJump| match (&jump.label, label) {
+        (None, _) => true,
+        (Some(target), Some(label)) => target.lexeme == label.lexeme,
+        (Some(_), None) => false,
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(Interrupt::Break(jump)) if ours(&jump) => Ok(false),
+        Err(Interrupt::Continue(jump)) if ours(&jump) => Ok(true),
+        Err(interrupt) => Err(interrupt),
+    }
+}
+
+// What `for (var x in value)` walks over: a list's items, a map's keys or a
+// string's characters (ranges are counted out by Interp::for_in_items). Taken up front, so changing the list or map inside the
+// loop doesn't change what the loop visits.
+fn iteration_items(keyword: &Token, value: &Value) -> Result<Vec<Value>> {
+    match value {
+        Value::List(list) => Ok(list.lock().unwrap().clone()),
+        Value::Map(map) => Ok(map.lock().unwrap().iter().map(|(key, _)| key.to_value()).collect()),
+        Value::String(s) => Ok(s.chars().map(|c| Value::String(c.to_string())).collect()),
+        _ => Err(RuntimeError::new(keyword.clone(), format!(
+            "can only iterate over lists, maps, strings and ranges, not a {}", type_name(value))
+        )),
+    }
+}
+
+// see Interp::for_in_items
+enum Items {
+    Values(std::vec::IntoIter<Value>),
+    Range(f64, f64),  // the next number and where to stop
+}
+
+impl Iterator for Items {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Items::Values(values) => values.next(),
+            Items::Range(next, end) if *next < *end => {
+                *next += 1.0;
+                Some(Value::Number(*next - 1.0))
+            }
+            Items::Range(..) => None,
+        }
+    }
+}
+
+// Everything but `and` / `or`, which short circuit. Compound assignments
+// come here too, with their operator turned into the plain one.
+fn binary_op(operator: &Token, l: Value, r: Value) -> Result<Value> {
+    match (operator.token_type, &l, &r) {
+        (TokenType::EQUAL_EQUAL, _, _) => Ok(Value::Boolean(is_equal(&l, &r))),
+        (TokenType::BANG_EQUAL, _, _) => Ok(Value::Boolean(!is_equal(&l, &r))),
+        (TokenType::PLUS, Value::String(a), Value::String(b)) => Ok(Value::String(a.clone() + b)),
+        (_, Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (*a, *b);
+            match operator.token_type {
+                TokenType::GREATER => Ok(Value::Boolean(a > b)),
+                TokenType::GREATER_EQUAL => Ok(Value::Boolean(a >= b)),
+                TokenType::LESS => Ok(Value::Boolean(a < b)),
+                TokenType::LESS_EQUAL => Ok(Value::Boolean(a <= b)),
+                TokenType::MINUS => Ok(Value::Number(a - b)),
+                TokenType::SLASH => Ok(Value::Number(a / b)),
+                TokenType::STAR => Ok(Value::Number(a * b)),
+                TokenType::PLUS => Ok(Value::Number(a + b)),
+                // takes the sign of a, like C and JavaScript
+                TokenType::PERCENT => Ok(Value::Number(a % b)),
+                TokenType::STAR_STAR => Ok(Value::Number(a.powf(b))),
+                _ => Err(RuntimeError::new(operator.clone(), "Unexpected binary operator for numbers".to_string()))
+            }
+        }
+        (TokenType::PLUS, _, _) => Err(RuntimeError::new(operator.clone(), format!(
+            "operands of '+' must be two numbers or two strings, not a {} and a {}", type_name(&l), type_name(&r)
+        ))),
+        _ => Err(RuntimeError::new(operator.clone(), format!(
+            "operands of '{}' must be numbers, not a {} and a {}", operator.lexeme, type_name(&l), type_name(&r)
+        ))),
+    }
+}
+
+// `+=` -> `+` and so on, or None for a plain `=`
+fn compound_operator(operator: &Token) -> Option<Token> {
+    let token_type = match operator.token_type {
+        TokenType::PLUS_EQUAL => TokenType::PLUS,
+        TokenType::MINUS_EQUAL => TokenType::MINUS,
+        TokenType::STAR_EQUAL => TokenType::STAR,
+        TokenType::SLASH_EQUAL => TokenType::SLASH,
+        TokenType::PERCENT_EQUAL => TokenType::PERCENT,
+        _ => return None,
+    };
+    let lexeme = operator.lexeme.trim_end_matches('=').to_string();
+    Some(Token::new(token_type, lexeme, Literal::Nil, operator.line))
+}
+
+fn index_get(bracket: &Token, object: &Value, i: &Value) -> Result<Value> {
+    match object {
+        Value::List(list) => {
+            let items = list.lock().unwrap();
+            let i = list_index(bracket, i, items.len())?;
+            Ok(items[i].clone())
+        }
+        Value::Map(map) => {
+            let key = map_key(bracket, i)?;
+            match map.lock().unwrap().get(&key) {
+                Some(value) => Ok(value.clone()),
+                None => Err(RuntimeError::new(bracket.clone(), format!("key {} isn't in the map", stringify(i)))),
+            }
+        }
+        Value::Range(start, end) => {
+            let i = list_index(bracket, i, natives::range_len(*start, *end))?;
+            Ok(Value::Number(start + i as f64))
+        }
+        _ => Err(not_indexable(bracket, object)),
+    }
+}
+
+// The token a node has of its own, for step() to point at. Anything inside
+// it gets a step of its own, so there's no need to dig.
+fn expr_token(expr: &Expr) -> Option<&Token> {
+    match expr {
+        Expr::Assign(assign) => Some(&assign.name),
+        Expr::Conditional(conditional) => Some(&conditional.question),
+        Expr::Logical(logical) => Some(&logical.operator),
+        Expr::Binary(binary) => Some(&binary.operator),
+        Expr::Unary(unary) => Some(&unary.operator),
+        Expr::Call(call) => Some(&call.paren),
+        Expr::Lambda(lambda) => Some(&lambda.keyword),
+        Expr::List(list) => Some(&list.bracket),
+        Expr::Map(map) => Some(&map.brace),
+        Expr::Index(index) => Some(&index.bracket),
+        Expr::IndexAssign(index_assign) => Some(&index_assign.bracket),
+        Expr::Variable(token) => Some(token),
+        Expr::Grouping(_) | Expr::Literal(_) | Expr::Null => None,
+    }
+}
+
+fn stmt_token(stmt: &Stmt) -> Option<&Token> {
+    match stmt {
+        Stmt::Var(var) => Some(&var.name),
+        Stmt::Func(func_stmt) => Some(&func_stmt.name),
+        Stmt::While(while_stmt) => Some(&while_stmt.keyword),
+        Stmt::For(for_stmt) => Some(&for_stmt.keyword),
+        Stmt::ForIn(for_in) => Some(&for_in.keyword),
+        Stmt::Return(return_stmt) => Some(&return_stmt.keyword),
+        Stmt::Break(jump) | Stmt::Continue(jump) => Some(&jump.keyword),
+        Stmt::Throw(throw) => Some(&throw.keyword),
+        Stmt::Try(try_stmt) => Some(&try_stmt.keyword),
+        Stmt::Expression(_) | Stmt::Print(_) | Stmt::Block(_) | Stmt::If(_) => None,
+    }
+}
+
+fn not_indexable(bracket: &Token, object: &Value) -> RuntimeError {
+    RuntimeError::new(bracket.clone(), format!("can only index lists, maps and ranges, not a {}", type_name(object)))
+}
+
+fn map_key(bracket: &Token, key: &Value) -> Result<MapKey> {
+    MapKey::from_value(key).map_err(|message| RuntimeError::new(bracket.clone(), message))
+}
+
+// A whole number in 0..len, or an error pointing at the ']'.
+fn list_index(bracket: &Token, index: &Value, len: usize) -> Result<usize> {
+    let Value::Number(n) = index else {
+        return Err(RuntimeError::new(bracket.clone(), format!("list index must be a number, not a {}", type_name(index))));
+    };
+    if n.fract() != 0.0 || *n < 0.0 || *n >= len as f64 {
+        return Err(RuntimeError::new(bracket.clone(), format!(
+            "index {} is out of range for a list of length {len}", format_number(*n))
+        ));
+    }
+    Ok(*n as usize)
+}
+
 fn is_equal(a: &Value, b: &Value) -> bool {
     match (a, b) {
         (Value::Number(a), Value::Number(b)) => a == b,
         (Value::String(a), Value::String(b)) => a == b,
         (Value::Boolean(a), Value::Boolean(b)) => a == b,
         (Value::Nil, Value::Nil) => true,
+        // the same list / function, not just equal contents
+        (Value::List(a), Value::List(b)) => Arc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+        (Value::Callable(a), Value::Callable(b)) => Arc::ptr_eq(a, b),
+        // nothing to change in a range, so the same numbers are the same range
+        (Value::Range(a, b), Value::Range(c, d)) => a == c && b == d,
         _ => false
     }
 }
 
-fn is_truthy(thing: &Value) -> bool {
+pub fn is_truthy(thing: &Value) -> bool {
 
     match thing {
         Value::Nil => false,
@@ -319,15 +1143,118 @@ fn is_truthy(thing: &Value) -> bool {
     }
 }
 
+// The details are boxed so that an Err doesn't make every Result that could
+// hold one (nearly all of them, here) as big as the error. They're reached
+// through it, as `err.message`.
 #[derive(Debug, Clone)]
-pub struct RuntimeError{
+pub struct RuntimeError(Box<ErrorDetails>);
+
+#[derive(Debug, Clone)]
+pub struct ErrorDetails {
     pub token: Token,
-    pub message: String
+    pub message: String,
+    // what a `throw` threw, None for the interpreter's own errors
+    pub value: Option<Value>,
+    // the calls it escaped from, innermost first. Filled in as it leaves
+    // the first of them, so empty for an error raised at the top level.
+    pub stack: Vec<Frame>,
+    // set if the script didn't go wrong but ran into one of Interp's limits
+    pub limit: Option<Limit>,
+}
+
+impl std::ops::Deref for RuntimeError {
+    type Target = ErrorDetails;
+
+    fn deref(&self) -> &ErrorDetails {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for RuntimeError {
+    fn deref_mut(&mut self) -> &mut ErrorDetails {
+        &mut self.0
+    }
+}
+
+// The limits whoever runs a script can put on it, see Interp::set_fuel,
+// set_deadline and set_memory_limit. `catch` doesn't catch these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Deadline,
+    Memory,
+}
+
+impl Limit {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Limit::Fuel => "Out of fuel",
+            Limit::Deadline => "Timed out",
+            Limit::Memory => "Out of memory",
+        }
+    }
 }
 
 impl RuntimeError {
-    fn new(token: Token, message: String) -> Self {
-        Self { token, message }
+    pub fn new(token: Token, message: String) -> Self {
+        Self(Box::new(ErrorDetails { token, message, value: None, stack: Vec::new(), limit: None }))
+    }
+
+    // `throw value;`. A map with a "message" in it, like the ones `catch`
+    // hands out, keeps that message if it goes uncaught.
+    pub fn thrown(token: Token, value: Value) -> Self {
+        let message = match &value {
+            Value::String(s) => Some(s.clone()),
+            Value::Map(map) => match map.lock().unwrap().get(&MapKey::String("message".to_string())) {
+                Some(Value::String(s)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let message = message.unwrap_or_else(|| stringify(&value));
+        Self(Box::new(ErrorDetails { token, message, value: Some(value), stack: Vec::new(), limit: None }))
+    }
+
+    // "[line 3] in f()" for each frame, innermost first, ending with the
+    // script itself. A frame's line is where it had got to: the error's
+    // own line for the innermost, after that the line of the call into the
+    // frame inside it. A run of the same line, as runaway recursion gives,
+    // is shown once with a count.
+    pub fn traceback(&self) -> Vec<String> {
+        let mut entries = Vec::new();
+        let mut line = self.token.line;
+        for frame in &self.stack {
+            entries.push(format!("[line {line}] in {}()", frame.function));
+            line = frame.call_site.line;
+        }
+        entries.push(format!("[line {line}] in script"));
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut repeats = 0;
+        for entry in entries {
+            if lines.last() == Some(&entry) {
+                repeats += 1;
+                continue;
+            }
+            if repeats > 0 {
+                lines.push(format!("[previous line repeated {repeats} more time{}]", if repeats == 1 { "" } else { "s" }));
+                repeats = 0;
+            }
+            lines.push(entry);
+        }
+        lines
+    }
+
+    // what `catch (e)` sets e to: the thrown value, or for the interpreter's
+    // own errors a map like {"message": "...", "line": 3}
+    pub fn to_value(&self) -> Value {
+        if let Some(value) = &self.value {
+            return value.clone();
+        }
+        let mut map = LoxMap::new();
+        map.insert(MapKey::String("message".to_string()), Value::String(self.message.clone()));
+        map.insert(MapKey::String("line".to_string()), Value::Number(self.token.line as f64));
+        Value::Map(Arc::new(Mutex::new(map)))
     }
 }
 
@@ -368,7 +1295,324 @@ mod test {
     use crate::token_type::{Token, TokenType};
     use crate::scanner::Scanner;
     use crate::lox::ast::parser::Parser;
-    use crate::lox::ast::interpreter::{Interp, ExprVisitor, is_truthy, RuntimeError};
+    use crate::lox::ast::interpreter::{Interp, ExprVisitor, is_truthy, RuntimeError, format_number, stringify, Interrupt, Limit, StmtVisitor, DEFAULT_MAX_CALL_DEPTH};
+    use crate::lox::ast::natives::FakeClock;
+    use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(format_number(-0.25), "-0.25");
+        assert_eq!(format_number(1.0 / 0.0), "inf");
+        assert_eq!(format_number(-1.0 / 0.0), "-inf");
+        assert_eq!(format_number(f64::NAN), "nan");
+        assert_eq!(format_number(123456789012345680000.0), "123456789012345680000");
+        assert_eq!(format_number(1e21), "1e+21");
+        assert_eq!(format_number(-2.5e300), "-2.5e+300");
+        assert_eq!(format_number(0.000001), "0.000001");
+        assert_eq!(format_number(1.5e-7), "1.5e-7");
+    }
+
+    // runs `program`, then gives the value of `expr` afterwards (or the error)
+    fn run_then_eval(program: &str, expr: &str) -> String {
+        let mut interp = Interp::new();
+        let stmts = Parser::new(Scanner::new(program.to_string()).scan_tokens()).parse().unwrap();
+        interp.interpret_stmts(&stmts).unwrap();
+        let expr = Parser::new(Scanner::new(expr.to_string()).scan_tokens()).parse_lone_expr().unwrap();
+        match interp.evaluate(&expr) {
+            Ok(value) => stringify(&value),
+            Err(err) => format!("error: {}", err.message),
+        }
+    }
+
+    #[test]
+    fn test_for_in() {
+        let out = "var out = [];";
+        assert_eq!(run_then_eval(&format!("{out} for (var x in [1, 2, 3]) push(out, x * 10);"), "out"), "[10, 20, 30]");
+        assert_eq!(run_then_eval(&format!("{out} for (var k in {{\"b\": 1, \"a\": 2}}) push(out, k);"), "out"), "[\"b\", \"a\"]");
+        assert_eq!(run_then_eval(&format!("{out} for (var c in \"hé!\") push(out, c);"), "out"), "[\"h\", \"é\", \"!\"]");
+        assert_eq!(run_then_eval("var total = 0; for (var i in range(0, 5)) total = total + i;", "total"), "10");
+        // a range is counted out, not made into a list first, wherever it came from
+        assert_eq!(run_then_eval("var n = 0; for (var i in range(0.5, 1000000000000)) { if (i > 3) break; n += i; }", "n"), "4.5");
+        assert_eq!(run_then_eval("var r = range(0, 1000000000000); var n = 0; for (var i in r) { if (i > 3) break; n += i; }", "n"), "6");
+        assert_eq!(run_then_eval("var r = range; var n = 0; for (var i in r(0, 3)) n += i;", "n"), "3");
+        assert_eq!(run_then_eval("fun range(a, b) { return [b]; } var n = 0; for (var i in range(0, 3)) n += i;", "n"), "3");
+        // changing the list inside the loop doesn't change what gets visited
+        assert_eq!(run_then_eval("var xs = [1, 2]; for (var x in xs) push(xs, x);", "xs"), "[1, 2, 1, 2]");
+
+        // each time round gets its own x
+        let closures = "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); }";
+        assert_eq!(run_then_eval(closures, "fs[0]() + fs[2]()"), "4");
+        // and it's gone after the loop
+        assert_eq!(run_then_eval("for (var x in [1]) {}", "x"), "error: Undefined variable 'x'");
+
+        let stmts = Parser::new(Scanner::new("for (var x in 3) {}".to_string()).scan_tokens()).parse().unwrap();
+        let Err(Interrupt::Error(err)) = Interp::new().visit_statement(&stmts[0]) else { panic!("expected an error") };
+        assert_eq!(err.message, "can only iterate over lists, maps, strings and ranges, not a number");
+        assert_eq!(err.token.lexeme, "in");
+        let stmts = Parser::new(Scanner::new("for (var i in range(0, \"3\")) {}".to_string()).scan_tokens()).parse().unwrap();
+        let Err(Interrupt::Error(err)) = Interp::new().visit_statement(&stmts[0]) else { panic!("expected an error") };
+        assert_eq!(err.message, "range: argument 2 should be a number, not a string");
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let out = "var out = [];";
+        assert_eq!(run_then_eval(&format!("{out} var i = 0; while (true) {{ i = i + 1; if (i > 3) break; push(out, i); }}"), "out"), "[1, 2, 3]");
+        // the increment still runs after a continue, or this would never end
+        assert_eq!(run_then_eval(&format!("{out} for (var i = 0; i < 5; i = i + 1) {{ if (i == 2) continue; push(out, i); }}"), "out"), "[0, 1, 3, 4]");
+        assert_eq!(run_then_eval(&format!("{out} for (var x in range(0, 5)) {{ if (x == 1) continue; if (x == 3) break; push(out, x); }}"), "out"), "[0, 2]");
+
+        let nested = "var out = []; outer: for (var i in range(0, 3)) { for (var j in range(0, 3)) { \
+                      if (j == 1) continue outer; if (i == 2) break outer; push(out, [i, j]); } }";
+        assert_eq!(run_then_eval(nested, "out"), "[[0, 0], [1, 0]]");
+        // an unlabelled break only leaves the inner loop
+        let inner = "var n = 0; for (var i in range(0, 3)) { while (true) { n = n + 1; break; } }";
+        assert_eq!(run_then_eval(inner, "n"), "3");
+        // a return goes straight through the loops
+        let early = "fun find(xs, y) { var i = 0; for (var x in xs) { if (x == y) return i; i = i + 1; } return -1; }";
+        assert_eq!(run_then_eval(early, "find([5, 6, 7], 6)"), "1");
+        // only the resolver stops these, and this hasn't been through it
+        assert_eq!(run_then_eval("", "(fun () { break; })()"), "error: can't use 'break' outside a loop");
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(run_then_eval("", "[7 % 3, -7 % 3, 2 ** 10, 2 ** 3 ** 2, -2 ** 2, 2 ** -1]"), "[1, -1, 1024, 512, -4, 0.5]");
+        assert_eq!(run_then_eval("", "[true ? 1 : 2, nil ? 1 : 0 ? 2 : 3]"), "[1, 2]");
+        // only the branch taken is evaluated
+        assert_eq!(run_then_eval("var n = 0; true ? 1 : (n = 5);", "n"), "0");
+        assert_eq!(run_then_eval("var x = 10; x += 5; x -= 1; x *= 2; x /= 4; x %= 4;", "x"), "3");
+        assert_eq!(run_then_eval("var s = \"a\"; s += \"b\";", "s"), "\"ab\"");
+        assert_eq!(run_then_eval("var m = {\"n\": [1]}; m[\"n\"][0] += 2; m[\"n\"] += [];", "m"), "{\"n\": [3]}");
+
+        assert_eq!(run_then_eval("", "1 + \"a\""), "error: operands of '+' must be two numbers or two strings, not a number and a string");
+        assert_eq!(run_then_eval("", "nil % 2"), "error: operands of '%' must be numbers, not a nil and a number");
+        assert_eq!(run_then_eval("var m = {};", "m[\"k\"] += 1"), "error: key \"k\" isn't in the map");
+    }
+
+    #[test]
+    fn test_nil_safe_operators() {
+        let setup = "var none; var m = {\"a\": [1, 2]}; var calls = 0; fun count() { calls += 1; return calls; }";
+        assert_eq!(run_then_eval(setup, "[none ?? 1, false ?? 1, 0 ?? 1, none ?? none ?? 2]"), "[1, false, 0, 2]");
+        assert_eq!(run_then_eval(setup, "[m?.[\"a\"]?.[1], none?.[\"a\"], none?.[0]?.[1], none?.(1)]"), "[2, nil, nil, nil]");
+        // the right hand side / index / arguments aren't evaluated when skipped
+        assert_eq!(run_then_eval(&format!("{setup} 1 ?? count(); none?.[count()]; none?.(count());"), "calls"), "0");
+        assert_eq!(run_then_eval(&format!("{setup} none ?? count(); m?.[count()];"), "calls"), "2");
+        // a nil at ?. skips the rest of the chain, calls and indexes alike
+        assert_eq!(run_then_eval(setup, "[none?.[0][1], none?.[\"f\"](), none?.(1)[2](3), none?.[count()][count()]]"), "[nil, nil, nil, nil]");
+        assert_eq!(run_then_eval(&format!("{setup} none?.[0](count())[count()];"), "calls"), "0");
+        // but only a nil the ?. itself found, not one a later link gives back
+        assert_eq!(run_then_eval(setup, "m?.[\"b\"][0]"), "error: key \"b\" isn't in the map");
+        assert_eq!(run_then_eval(setup, "m?.[\"a\"][5]"), "error: index 5 is out of range for a list of length 2");
+        // and brackets end the chain
+        assert_eq!(run_then_eval(setup, "(none?.[0])[1]"), "error: can only index lists, maps and ranges, not a nil");
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let caught = |body: &str| run_then_eval(&format!("var caught; try {{ {body} }} catch (e) {{ caught = e; }}"), "caught");
+        assert_eq!(caught("throw [1, 2];"), "[1, 2]");
+        assert_eq!(caught("var x = 1 - \"a\";"), "{\"message\": \"operands of '-' must be numbers, not a number and a string\", \"line\": 1}");
+        assert_eq!(run_then_eval("var m; try { missing; } catch (e) { m = e[\"message\"]; }", "m"), "\"Undefined variable 'missing'\"");
+        // thrown from inside a function call, through an expression
+        assert_eq!(caught("fun f() { throw \"deep\"; } var y = 1 + f();"), "\"deep\"");
+
+        let log = "var log = []; fun note(x) { push(log, x); }";
+        let finally_on_return = "fun f() { try { return 1; } finally { note(\"finally\"); } } note(f());";
+        assert_eq!(run_then_eval(&format!("{log} {finally_on_return}"), "log"), "[\"finally\", 1]");
+        let finally_on_break = "while (true) { try { break; } catch (e) { note(\"caught\"); } finally { note(\"finally\"); } }";
+        assert_eq!(run_then_eval(&format!("{log} {finally_on_break}"), "log"), "[\"finally\"]");
+        let rethrown = "try { try { throw 1; } catch (e) { throw e + 1; } finally { note(\"inner\"); } } catch (e) { note(e); }";
+        assert_eq!(run_then_eval(&format!("{log} {rethrown}"), "log"), "[\"inner\", 2]");
+        // a return in the finally wins over the error
+        assert_eq!(run_then_eval("fun f() { try { throw 1; } finally { return 2; } }", "f()"), "2");
+
+        let stmts = Parser::new(Scanner::new("throw {\"message\": \"bad\"};".to_string()).scan_tokens()).parse().unwrap();
+        let Err(Interrupt::Error(err)) = Interp::new().visit_statement(&stmts[0]) else { panic!("expected an error") };
+        assert_eq!(err.message, "bad");
+    }
+
+    // the error that stops the program, as interpret_stmts would report it
+    fn uncaught_error(program: &str) -> RuntimeError {
+        uncaught_error_in(&mut Interp::new(), program)
+    }
+
+    // for an Interp that's been set up specially
+    fn uncaught_error_in(interp: &mut Interp, program: &str) -> RuntimeError {
+        let stmts = Parser::new(Scanner::new(program.to_string()).scan_tokens()).parse().unwrap();
+        for stmt in &stmts {
+            if let Err(Interrupt::Error(err)) = interp.visit_statement(stmt) {
+                assert!(interp.frames().is_empty());
+                return err;
+            }
+        }
+        panic!("expected an error")
+    }
+
+    fn eval_in(interp: &mut Interp, expr: &str) -> String {
+        let expr = Parser::new(Scanner::new(expr.to_string()).scan_tokens()).parse_lone_expr().unwrap();
+        stringify(&interp.evaluate(&expr).unwrap())
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let program = "fun inner(x) {\n  return x + nil;\n}\nfun outer() {\n  return inner(1);\n}\n\
+                       print map([1], fun (v) {\n  return outer();\n});";
+        let err = uncaught_error(program);
+        let frames: Vec<(&str, usize)> = err.stack.iter().map(|f| (f.function.as_str(), f.call_site.line)).collect();
+        assert_eq!(frames, vec![("inner", 5), ("outer", 8), ("anonymous", 9), ("map", 9)]);
+        assert_eq!(
+            err.traceback(),
+            vec!["[line 2] in inner()", "[line 5] in outer()", "[line 8] in anonymous()", "[line 9] in map()", "[line 9] in script"]
+        );
+
+        assert_eq!(uncaught_error("var a = 1;\nprint -nil;").traceback(), vec!["[line 2] in script"]);
+        // a rethrow starts a new traceback from where it was thrown again
+        let rethrown = "fun f() { throw \"x\"; }\nfun g() {\n try { f(); } catch (e) {\n throw e; }\n}\ng();";
+        assert_eq!(uncaught_error(rethrown).traceback(), vec!["[line 4] in g()", "[line 6] in script"]);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut interp = Interp::new();
+        interp.set_max_call_depth(50);
+        let program = "fun down(n) {\n  return down(n + 1);\n}\n\
+                       var caught; try { down(0); } catch (e) { caught = e[\"message\"]; }\n\
+                       fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); }\n\
+                       var counted = count(49);\n\
+                       down(0);";
+        let err = uncaught_error_in(&mut interp, program);
+        assert_eq!(err.message, "Stack overflow");
+        assert_eq!(err.stack.len(), 50);
+        assert_eq!(err.traceback(), vec!["[line 2] in down()", "[previous line repeated 49 more times]", "[line 7] in script"]);
+        assert_eq!(eval_in(&mut interp, "[caught, counted]"), "[\"Stack overflow\", 49]");
+
+        // Interp::new() on a thread the size std::thread::spawn makes: it's
+        // the stack running low that stops this, well short of 1000 calls
+        std::thread::Builder::new().stack_size(2 << 20).spawn(|| {
+            let mut interp = Interp::new();
+            let program = "fun down(n) { return [down(n + 1)]; }\n\
+                           var caught; try { down(0); } catch (e) { caught = e[\"message\"]; }\n\
+                           down(0);";
+            let err = uncaught_error_in(&mut interp, program);
+            assert_eq!((err.message.as_str(), err.token.line), ("Stack overflow", 1));
+            assert!(err.stack.len() < DEFAULT_MAX_CALL_DEPTH);
+            assert_eq!(eval_in(&mut interp, "caught"), "\"Stack overflow\"");
+        }).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut interp = Interp::new();
+        interp.set_fuel(Some(1000));
+        let err = uncaught_error_in(&mut interp, "var caught = false;\ntry {\n  while (true) {}\n} catch (e) {\n  caught = true;\n}");
+        assert_eq!((err.message.as_str(), err.limit, err.token.line), ("Out of fuel", Some(Limit::Fuel), 3));
+        assert_eq!(interp.fuel(), Some(0));
+        assert_eq!(interp.steps(), 1000 + 1);
+
+        interp.add_fuel(10);
+        assert_eq!(eval_in(&mut interp, "caught"), "false");
+        assert_eq!(interp.fuel(), Some(9));
+
+        // handing out a slice at a time, the way a scheduler would
+        let refuels = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&refuels);
+        interp.set_fuel(Some(0));
+        interp.set_refuel(Some(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            50
+        })));
+        let stmts = Parser::new(Scanner::new("var n = 0; for (var i = 0; i < 100; i += 1) n += i;".to_string()).scan_tokens()).parse().unwrap();
+        interp.interpret_stmts(&stmts).unwrap();
+        assert!(refuels.load(Ordering::SeqCst) > 10);
+        assert_eq!(eval_in(&mut interp, "n"), "4950");
+
+        // 0 from the refuel stops it
+        interp.set_refuel(Some(Box::new(|| 0)));
+        interp.set_fuel(Some(5));
+        assert_eq!(uncaught_error_in(&mut interp, "while (true) {}").limit, Some(Limit::Fuel));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut interp = Interp::new();
+        interp.set_memory_limit(Some(1 << 20));
+        let err = uncaught_error_in(&mut interp, "var s = \"x\";\nvar caught = false;\ntry {\n  while (true) s = s + s;\n} catch (e) {\n  caught = true;\n}");
+        assert_eq!((err.message.as_str(), err.limit, err.token.line), ("Out of memory", Some(Limit::Memory), 4));
+        assert_eq!(eval_in(&mut interp, "[len(s), caught]"), "[524288, false]");
+        assert!(interp.heap_size() > 524288 && interp.heap_size() < 1 << 20);
+
+        // garbage doesn't count, only what can still be reached
+        let mut interp = Interp::new();
+        interp.set_memory_limit(Some(1 << 20));
+        let stmts = Parser::new(Scanner::new("var n = 0; for (var i = 0; i < 20000; i += 1) { var s = \"abc\" + \"def\"; var l = [s, s, {\"s\": s}]; n += len(l); }".to_string()).scan_tokens()).parse().unwrap();
+        interp.interpret_stmts(&stmts).unwrap();
+        assert_eq!(eval_in(&mut interp, "n"), "60000");
+
+        // natives that build things are charged for them too, before they
+        // build them if they could be huge
+        let err = uncaught_error_in(&mut interp, "var l = [];\nwhile (true) push(l, \"a string of a few dozen bytes\");");
+        assert_eq!((err.limit, err.traceback()), (Some(Limit::Memory), vec!["[line 2] in push()".to_string(), "[line 2] in script".to_string()]));
+
+        let mut interp = Interp::new();
+        interp.set_memory_limit(Some(1 << 20));
+        assert_eq!(uncaught_error_in(&mut interp, "var r = range(0, 1000);\nvar l = map(range(0, 1000000000000), fun (x) { return x; });").token.line, 2);
+        let program = "var s = \"aaaaaaaaaaaaaaaaaaaa\";\nvar t = replace(s, \"a\", s);\nvar u = replace(t, \"a\", t);\nvar v = replace(u, \"a\", u);";
+        assert_eq!(uncaught_error_in(&mut interp, program).token.line, 4);
+        assert_eq!(eval_in(&mut interp, "[len(r), len(u)]"), "[1000, 160000]");
+        assert_eq!(uncaught_error_in(&mut interp, "var j = join(r, \",\");\nj = join(r, u);").token.line, 2);
+        assert_eq!(eval_in(&mut interp, "len(j)"), "3889");
+        // 512KB, and another copy of it doesn't fit
+        let half = "var s = \"x\"; for (var i = 0; i < 19; i += 1) s = s + s;";
+        for native in ["upper(s)", "lower(s)", "trim(s)", "substr(s, 0, len(s))"] {
+            let mut interp = Interp::new();
+            interp.set_memory_limit(Some(1 << 20));
+            let err = uncaught_error_in(&mut interp, &format!("{half}\nvar t = {native};"));
+            let called = format!("[line 2] in {}()", &native[..native.find('(').unwrap()]);
+            assert_eq!((err.limit, err.traceback()), (Some(Limit::Memory), vec![called, "[line 2] in script".to_string()]));
+        }
+
+        // what's being built up and isn't in a variable yet counts too, so
+        // it's stopped not long after passing the limit: 16 copies of a
+        // 64KB string make 1MB
+        let setup = "var s = \"x\"; for (var i = 0; i < 16; i += 1) s = s + s; var n = 0; fun big() { n += 1; return s + \"y\"; }";
+        for building in ["map(range(0, 400), fun (x) { return big(); })", &format!("[{}]", vec!["big()"; 400].join(", "))] {
+            let mut interp = Interp::new();
+            interp.set_memory_limit(Some(1 << 20));
+            let err = uncaught_error_in(&mut interp, &format!("{setup}\nvar built = {building};"));
+            assert_eq!(err.limit, Some(Limit::Memory), "{building}");
+            let n: usize = eval_in(&mut interp, "n").parse().unwrap();
+            assert!(n <= 19, "{building} made {n} strings");
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut interp = Interp::new();
+        interp.set_time_source(Box::new(FakeClock::new(0.0)));
+        interp.set_deadline(Some(Duration::from_millis(50)));
+        let err = uncaught_error_in(&mut interp, "var slept = 0;\nwhile (true) {\n  sleep(10);\n  slept += 1;\n}");
+        assert_eq!((err.message.as_str(), err.limit, err.token.line), ("Timed out", Some(Limit::Deadline), 3));
+
+        // sleep() stops at the deadline, and catch doesn't see it
+        interp.set_deadline(Some(Duration::from_millis(1000)));
+        let err = uncaught_error_in(&mut interp, "try { sleep(60000); } catch (e) { slept = -1; }");
+        assert_eq!(err.limit, Some(Limit::Deadline));
+        interp.set_deadline(None);
+        assert_eq!(eval_in(&mut interp, "[clock() * 1000, slept]"), "[1050, 4]");
+    }
+
+    #[test]
+    fn test_lambdas() {
+        assert_eq!(run_then_eval("var k = 3;", "map([1, 2], (x) => x * k)"), "[3, 6]");
+        assert_eq!(run_then_eval("", "reduce([1, 2, 3], fun (a, b) { return a + b; }, 0)"), "6");
+        // each call of the outer function makes a closure over its own n
+        let counter = "fun counter() { var n = 0; return () => n = n + 1; } var c = counter(); c(); c(); var d = counter();";
+        assert_eq!(run_then_eval(counter, "[c(), d()]"), "[3, 1]");
+        assert_eq!(run_then_eval("var f = fun () {};", "f"), "<fn anonymous>");
+    }
 
     #[test]
     fn test_parser2() {
@@ -424,7 +1668,7 @@ mod test {
         println!("tokens: {tokens:?}");
 
         let mut my_parser = Parser::new(tokens);
-        let stmts = my_parser.parse();
+        let stmts = my_parser.parse().unwrap();
         for stmt in &stmts{
             println!("stmt: {stmt:?}");
         }
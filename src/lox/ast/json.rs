@@ -0,0 +1,897 @@
+// JSON (de)serialisation of the AST, for tools that want to work on Lox
+// programs without reimplementing the parser.
+//
+// The layout is what serde's derive would give with its default, externally
+// tagged enums, so it should look familiar:
+//
+//   program   [Stmt, ...]
+//
+//   Stmt      {"Expression": Expr}
+//             {"Print": Expr}
+//             {"Var": {"name": Token, "initializer": Expr}}
+//             {"Block": [Stmt, ...]}
+//             {"If": {"condition": Expr, "if_branch": Stmt, "else_branch": Stmt | null}}
+//             {"While": {"label": Token | null, "keyword": Token, "condition": Expr, "body": Stmt}}
+//             {"For": {"label": Token | null, "keyword": Token, "initializer": Stmt | null,
+//                      "condition": Expr | null, "increment": Expr | null, "body": Stmt}}
+//             {"ForIn": {"label": Token | null, "name": Token, "keyword": Token, "iterable": Expr,
+//                        "body": Stmt}}
+//             {"Func": FuncStmt}
+//             {"Return": {"keyword": Token, "value": Expr}}
+//             {"Break": {"keyword": Token, "label": Token | null}}
+//             {"Continue": {"keyword": Token, "label": Token | null}}
+//             {"Throw": {"keyword": Token, "value": Expr}}
+//             {"Try": {"keyword": Token, "body": [Stmt, ...], "catch": Catch | null,
+//                      "finally": [Stmt, ...] | null}}
+//
+//   FuncStmt  {"name": Token, "parameters": [Token, ...], "body": [Stmt, ...]}
+//   Catch     {"name": Token, "body": [Stmt, ...]}
+//
+//   Expr      {"Assign": {"name": Token, "operator": Token, "value": Expr}}   (operator is = or +=, -=, ...)
+//             {"Conditional": {"condition": Expr, "question": Token, "then": Expr, "else": Expr}}
+//             {"Logical": {"operator": Token, "left": Expr, "right": Expr}}
+//             {"Binary": {"operator": Token, "left": Expr, "right": Expr}}
+//             {"Unary": {"operator": Token, "right": Expr}}
+//             {"Call": {"callee": Expr, "paren": Token, "arguments": [Expr, ...], "optional": Token | null}}
+//             {"Lambda": {"keyword": Token, "parameters": [Token, ...], "body": [Stmt, ...],
+//                         "arrow": bool}}   (an arrow's body is [{"Return": ...}])
+//             {"List": {"bracket": Token, "elements": [Expr, ...]}}
+//             {"Map": {"brace": Token, "entries": [[Expr, Expr], ...]}}   (key, value pairs)
+//             {"Index": {"object": Expr, "bracket": Token, "index": Expr, "optional": Token | null}}
+//             {"IndexAssign": {"object": Expr, "bracket": Token, "index": Expr, "operator": Token,
+//                              "value": Expr}}
+//             {"Grouping": Expr}
+//             {"Literal": Literal}
+//             {"Variable": Token}
+//             "Null"                      (the initializer of `var x;`, the value of `return;`)
+//
+//   Token     {"token_type": "IDENTIFIER", "lexeme": "x", "literal": Literal, "line": 3}
+//             token_type is the TokenType variant name, e.g. "PLUS", "EQUAL_EQUAL"
+//
+//   Literal   {"String": "hi"} | {"Number": 1.5} | {"Boolean": true} | "Nil"
+//             a literal too big for a double (400 digits, say) is {"Number": "inf"}
+//
+// Only the operator tokens' token_type is looked at when running a program;
+// lexeme and line are used for error messages.
+
+use std::fmt;
+
+use crate::token_type::{Literal, Token, TokenType};
+
+use super::interpreter::format_number;
+use super::parser::MAX_NESTING;
+use super::{Assign, Binary, Call, CatchClause, Conditional, Expr, ForInStmt, ForStmt, FuncStmt, Grouping, IfStmt, Index, IndexAssign, Lambda, ListExpr, Logical, LoopJump, MapExpr, ReturnStmt, Stmt, ThrowStmt, TryStmt, Unary, Variable, WhileStmt};
+
+pub fn stmts_to_json(stmts: &Vec<Stmt>) -> String {
+    stmts.to_json().pretty(0) + "\n"
+}
+
+pub fn stmts_from_json(text: &str) -> Result<Vec<Stmt>, JsonError> {
+    Vec::<Stmt>::from_json(&Json::parse(text)?)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),  // in insertion order
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonError {
+    pub message: String,
+}
+
+impl JsonError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JSON error: {}", self.message)
+    }
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> Result<Self, JsonError>;
+}
+
+impl Json {
+    fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    // {"tag": payload}
+    fn tagged(tag: &str, payload: Json) -> Json {
+        Json::object(vec![(tag, payload)])
+    }
+
+    fn field(&self, name: &str) -> Result<&Json, JsonError> {
+        if let Json::Object(fields) = self {
+            if let Some((_, value)) = fields.iter().find(|(k, _)| k == name) {
+                return Ok(value);
+            }
+        }
+        Err(JsonError::new(format!("expected an object with field '{name}', got {}", self.compact())))
+    }
+
+    // the (tag, payload) of {"tag": payload}, or (tag, null) for a bare "tag"
+    fn variant(&self) -> Result<(&str, &Json), JsonError> {
+        match self {
+            Json::String(tag) => Ok((tag, &Json::Null)),
+            Json::Object(fields) if fields.len() == 1 => Ok((&fields[0].0, &fields[0].1)),
+            _ => Err(JsonError::new(format!("expected an enum variant, got {}", self.compact()))),
+        }
+    }
+
+    pub fn compact(&self) -> String {
+        match self {
+            Json::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.compact()).collect();
+                format!("[{}]", items.join(","))
+            }
+            Json::Object(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}:{}", quote(k), v.compact())).collect();
+                format!("{{{}}}", fields.join(","))
+            }
+            _ => self.scalar(),
+        }
+    }
+
+    // two spaces per level; empty containers and scalars stay on one line
+    pub fn pretty(&self, level: usize) -> String {
+        let indent = "  ".repeat(level + 1);
+        let closing = "  ".repeat(level);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                let items: Vec<String> = items.iter().map(|item| format!("{indent}{}", item.pretty(level + 1))).collect();
+                format!("[\n{}\n{closing}]", items.join(",\n"))
+            }
+            Json::Object(fields) if !fields.is_empty() => {
+                let fields: Vec<String> = fields.iter()
+                    .map(|(k, v)| format!("{indent}{}: {}", quote(k), v.pretty(level + 1)))
+                    .collect();
+                format!("{{\n{}\n{closing}}}", fields.join(",\n"))
+            }
+            _ => self.compact(),
+        }
+    }
+
+    fn scalar(&self) -> String {
+        match self {
+            Json::Null => "null".to_string(),
+            Json::Bool(b) => b.to_string(),
+            // JSON has no inf/nan; Literal writes them as strings instead
+            Json::Number(n) if !n.is_finite() => "null".to_string(),
+            Json::Number(n) => n.to_string(),
+            Json::String(s) => quote(s),
+            Json::Array(_) => "[]".to_string(),
+            Json::Object(_) => "{}".to_string(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut reader = Reader { chars: text.chars().collect(), current: 0, depth: 0 };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if reader.current < reader.chars.len() {
+            return Err(reader.error("trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+// recursive descent over the characters, like the Scanner but for JSON
+struct Reader {
+    chars: Vec<char>,
+    current: usize,
+    // how many values we're inside, see MAX_DEPTH
+    depth: usize,
+}
+
+// A file from some other tool can nest as deeply as it likes, so like the
+// parser we stop at a limit rather than overflow the stack. Anything the
+// parser accepts fits: a level of nesting there is at most four of JSON
+// (a map entry, {"Map": {"entries": [[key, value]]}}), plus a few for the
+// program, the statement and the token at the bottom.
+const MAX_DEPTH: usize = 4 * MAX_NESTING + 4;
+
+impl Reader {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError::new(format!("{message} at character {}", self.current))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.current += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.current += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{c}'")))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            if self.peek() != Some(expected) {
+                return Err(self.error(&format!("expected '{word}'")));
+            }
+            self.current += 1;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(&format!("nested too deeply, the limit is {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let value = self.value_inner();
+        self.depth -= 1;
+        value
+    }
+
+    fn value_inner(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.current += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.current += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.current += 1,
+                        Some(']') => { self.current += 1; return Ok(Json::Array(items)); }
+                        _ => return Err(self.error("expected ',' or ']' in array")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.current += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.current += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.current += 1,
+                        Some('}') => { self.current += 1; return Ok(Json::Object(fields)); }
+                        _ => return Err(self.error("expected ',' or '}' in object")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a JSON value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.current;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                self.current += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.current].iter().collect();
+        text.parse().map(Json::Number).map_err(|_| self.error(&format!("bad number '{text}'")))
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        if self.peek() != Some('"') {
+            return Err(self.error("expected a string"));
+        }
+        self.current += 1;
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.current += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let Some(escape) = self.peek() else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.current += 1;
+                    match escape {
+                        '"' => out.push('"'),
+                        '\\' => out.push('\\'),
+                        '/' => out.push('/'),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair -> one scalar value
+                            if (0xD800..0xDC00).contains(&code) && self.chars.get(self.current..self.current + 2) == Some(&['\\', 'u']) {
+                                self.current += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            out.push(char::from_u32(code).ok_or_else(|| self.error("bad \\u escape"))?);
+                        }
+                        _ => return Err(self.error(&format!("bad escape '\\{escape}'"))),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits: String = self.chars.iter().skip(self.current).take(4).collect();
+        let code = u32::from_str_radix(&digits, 16).map_err(|_| self.error("bad \\u escape"))?;
+        self.current += 4;
+        Ok(code)
+    }
+}
+
+fn unknown_variant(kind: &str, tag: &str) -> JsonError {
+    JsonError::new(format!("unknown {kind} variant '{tag}'"))
+}
+
+// generic containers: Vec -> array, Option -> value or null, Box -> transparent,
+// a pair -> a two item array
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(|item| item.to_json()).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        match json {
+            Json::Array(items) => items.iter().map(T::from_json).collect(),
+            _ => Err(JsonError::new(format!("expected an array, got {}", json.compact()))),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(value) => value.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        match json {
+            Json::Null => Ok(None),
+            _ => Ok(Some(T::from_json(json)?)),
+        }
+    }
+}
+
+impl<A: ToJson, B: ToJson> ToJson for (A, B) {
+    fn to_json(&self) -> Json {
+        Json::Array(vec![self.0.to_json(), self.1.to_json()])
+    }
+}
+
+impl<A: FromJson, B: FromJson> FromJson for (A, B) {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        match json {
+            Json::Array(items) if items.len() == 2 => Ok((A::from_json(&items[0])?, B::from_json(&items[1])?)),
+            _ => Err(JsonError::new(format!("expected a two item array, got {}", json.compact()))),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Box<T> {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        Ok(Box::new(T::from_json(json)?))
+    }
+}
+
+fn bool_from_json(json: &Json) -> Result<bool, JsonError> {
+    match json {
+        Json::Bool(b) => Ok(*b),
+        _ => Err(JsonError::new(format!("expected true or false, got {}", json.compact()))),
+    }
+}
+
+fn string_from_json(json: &Json) -> Result<String, JsonError> {
+    match json {
+        Json::String(s) => Ok(s.clone()),
+        _ => Err(JsonError::new(format!("expected a string, got {}", json.compact()))),
+    }
+}
+
+fn number_from_json(json: &Json) -> Result<f64, JsonError> {
+    match json {
+        Json::Number(n) => Ok(*n),
+        _ => Err(JsonError::new(format!("expected a number, got {}", json.compact()))),
+    }
+}
+
+impl ToJson for Literal {
+    fn to_json(&self) -> Json {
+        match self {
+            Literal::String(s) => Json::tagged("String", Json::String(s.clone())),
+            Literal::Number(n) if n.is_infinite() => Json::tagged("Number", Json::String(format_number(*n))),
+            Literal::Number(n) => Json::tagged("Number", Json::Number(*n)),
+            Literal::Boolean(b) => Json::tagged("Boolean", Json::Bool(*b)),
+            Literal::Nil => Json::String("Nil".to_string()),
+        }
+    }
+}
+
+impl FromJson for Literal {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        match json.variant()? {
+            ("String", payload) => Ok(Literal::String(string_from_json(payload)?)),
+            ("Number", Json::String(s)) if s == "inf" => Ok(Literal::Number(f64::INFINITY)),
+            ("Number", Json::String(s)) if s == "-inf" => Ok(Literal::Number(f64::NEG_INFINITY)),
+            ("Number", payload) => Ok(Literal::Number(number_from_json(payload)?)),
+            ("Boolean", Json::Bool(b)) => Ok(Literal::Boolean(*b)),
+            ("Nil", _) => Ok(Literal::Nil),
+            (tag, _) => Err(unknown_variant("Literal", tag)),
+        }
+    }
+}
+
+impl ToJson for Token {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("token_type", Json::String(format!("{:?}", self.token_type))),
+            ("lexeme", Json::String(self.lexeme.clone())),
+            ("literal", self.literal.to_json()),
+            ("line", Json::Number(self.line as f64)),
+        ])
+    }
+}
+
+impl FromJson for Token {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        let name = string_from_json(json.field("token_type")?)?;
+        let token_type = TokenType::from_name(&name)
+            .ok_or_else(|| JsonError::new(format!("unknown token_type '{name}'")))?;
+        Ok(Token::new(
+            token_type,
+            string_from_json(json.field("lexeme")?)?,
+            Literal::from_json(json.field("literal")?)?,
+            number_from_json(json.field("line")?)? as usize,
+        ))
+    }
+}
+
+impl ToJson for Expr {
+    fn to_json(&self) -> Json {
+        match self {
+            Expr::Assign(assign) => Json::tagged("Assign", Json::object(vec![
+                ("name", assign.name.to_json()),
+                ("operator", assign.operator.to_json()),
+                ("value", assign.value.to_json()),
+            ])),
+            Expr::Conditional(conditional) => Json::tagged("Conditional", Json::object(vec![
+                ("condition", conditional.condition.to_json()),
+                ("question", conditional.question.to_json()),
+                ("then", conditional.then_branch.to_json()),
+                ("else", conditional.else_branch.to_json()),
+            ])),
+            Expr::Logical(logical) => Json::tagged("Logical", Json::object(vec![
+                ("operator", logical.operator.to_json()),
+                ("left", logical.left.to_json()),
+                ("right", logical.right.to_json()),
+            ])),
+            Expr::Binary(binary) => Json::tagged("Binary", Json::object(vec![
+                ("operator", binary.operator.to_json()),
+                ("left", binary.left.to_json()),
+                ("right", binary.right.to_json()),
+            ])),
+            Expr::Unary(unary) => Json::tagged("Unary", Json::object(vec![
+                ("operator", unary.operator.to_json()),
+                ("right", unary.right.to_json()),
+            ])),
+            Expr::Call(call) => Json::tagged("Call", Json::object(vec![
+                ("callee", call.callee.to_json()),
+                ("paren", call.paren.to_json()),
+                ("arguments", call.arguments.to_json()),
+                ("optional", call.optional.to_json()),
+            ])),
+            Expr::Lambda(lambda) => Json::tagged("Lambda", Json::object(vec![
+                ("keyword", lambda.keyword.to_json()),
+                ("parameters", lambda.parameters.to_json()),
+                ("body", lambda.body.to_json()),
+                ("arrow", Json::Bool(lambda.arrow)),
+            ])),
+            Expr::List(list) => Json::tagged("List", Json::object(vec![
+                ("bracket", list.bracket.to_json()),
+                ("elements", list.elements.to_json()),
+            ])),
+            Expr::Map(map) => Json::tagged("Map", Json::object(vec![
+                ("brace", map.brace.to_json()),
+                ("entries", map.entries.to_json()),
+            ])),
+            Expr::Index(index) => Json::tagged("Index", Json::object(vec![
+                ("object", index.object.to_json()),
+                ("bracket", index.bracket.to_json()),
+                ("index", index.index.to_json()),
+                ("optional", index.optional.to_json()),
+            ])),
+            Expr::IndexAssign(index_assign) => Json::tagged("IndexAssign", Json::object(vec![
+                ("object", index_assign.object.to_json()),
+                ("bracket", index_assign.bracket.to_json()),
+                ("index", index_assign.index.to_json()),
+                ("operator", index_assign.operator.to_json()),
+                ("value", index_assign.value.to_json()),
+            ])),
+            Expr::Grouping(grouping) => Json::tagged("Grouping", grouping.0.to_json()),
+            Expr::Literal(literal) => Json::tagged("Literal", literal.to_json()),
+            Expr::Variable(token) => Json::tagged("Variable", token.to_json()),
+            Expr::Null => Json::String("Null".to_string()),
+        }
+    }
+}
+
+impl FromJson for Expr {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        let (tag, payload) = json.variant()?;
+        Ok(match tag {
+            "Assign" => Expr::Assign(Assign {
+                name: Token::from_json(payload.field("name")?)?,
+                operator: Token::from_json(payload.field("operator")?)?,
+                value: Box::from_json(payload.field("value")?)?,
+            }),
+            "Conditional" => Expr::Conditional(Conditional {
+                condition: Box::from_json(payload.field("condition")?)?,
+                question: Token::from_json(payload.field("question")?)?,
+                then_branch: Box::from_json(payload.field("then")?)?,
+                else_branch: Box::from_json(payload.field("else")?)?,
+            }),
+            "Logical" => Expr::Logical(Logical {
+                operator: Token::from_json(payload.field("operator")?)?,
+                left: Box::from_json(payload.field("left")?)?,
+                right: Box::from_json(payload.field("right")?)?,
+            }),
+            "Binary" => Expr::Binary(Binary {
+                operator: Token::from_json(payload.field("operator")?)?,
+                left: Box::from_json(payload.field("left")?)?,
+                right: Box::from_json(payload.field("right")?)?,
+            }),
+            "Unary" => Expr::Unary(Unary {
+                operator: Token::from_json(payload.field("operator")?)?,
+                right: Box::from_json(payload.field("right")?)?,
+            }),
+            "Call" => Expr::Call(Call {
+                callee: Box::from_json(payload.field("callee")?)?,
+                paren: Token::from_json(payload.field("paren")?)?,
+                arguments: Vec::from_json(payload.field("arguments")?)?,
+                optional: Option::from_json(payload.field("optional")?)?,
+            }),
+            "Lambda" => Expr::Lambda(Lambda {
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                parameters: Vec::from_json(payload.field("parameters")?)?,
+                body: Vec::from_json(payload.field("body")?)?,
+                arrow: bool_from_json(payload.field("arrow")?)?,
+            }),
+            "List" => Expr::List(ListExpr {
+                bracket: Token::from_json(payload.field("bracket")?)?,
+                elements: Vec::from_json(payload.field("elements")?)?,
+            }),
+            "Map" => Expr::Map(MapExpr {
+                brace: Token::from_json(payload.field("brace")?)?,
+                entries: Vec::from_json(payload.field("entries")?)?,
+            }),
+            "Index" => Expr::Index(Index {
+                object: Box::from_json(payload.field("object")?)?,
+                bracket: Token::from_json(payload.field("bracket")?)?,
+                index: Box::from_json(payload.field("index")?)?,
+                optional: Option::from_json(payload.field("optional")?)?,
+            }),
+            "IndexAssign" => Expr::IndexAssign(IndexAssign {
+                object: Box::from_json(payload.field("object")?)?,
+                bracket: Token::from_json(payload.field("bracket")?)?,
+                index: Box::from_json(payload.field("index")?)?,
+                operator: Token::from_json(payload.field("operator")?)?,
+                value: Box::from_json(payload.field("value")?)?,
+            }),
+            "Grouping" => Expr::Grouping(Grouping(Box::from_json(payload)?)),
+            "Literal" => Expr::Literal(Literal::from_json(payload)?),
+            "Variable" => Expr::Variable(Token::from_json(payload)?),
+            "Null" => Expr::Null,
+            _ => return Err(unknown_variant("Expr", tag)),
+        })
+    }
+}
+
+impl ToJson for FuncStmt {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("parameters", self.parameters.to_json()),
+            ("body", self.body.to_json()),
+        ])
+    }
+}
+
+impl FromJson for FuncStmt {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        Ok(FuncStmt {
+            name: Token::from_json(json.field("name")?)?,
+            parameters: Vec::from_json(json.field("parameters")?)?,
+            body: Vec::from_json(json.field("body")?)?,
+        })
+    }
+}
+
+impl ToJson for CatchClause {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("body", self.body.to_json()),
+        ])
+    }
+}
+
+impl FromJson for CatchClause {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        Ok(CatchClause {
+            name: Token::from_json(json.field("name")?)?,
+            body: Vec::from_json(json.field("body")?)?,
+        })
+    }
+}
+
+impl ToJson for LoopJump {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("keyword", self.keyword.to_json()),
+            ("label", self.label.to_json()),
+        ])
+    }
+}
+
+impl FromJson for LoopJump {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        Ok(LoopJump {
+            keyword: Token::from_json(json.field("keyword")?)?,
+            label: Option::from_json(json.field("label")?)?,
+        })
+    }
+}
+
+impl ToJson for Stmt {
+    fn to_json(&self) -> Json {
+        match self {
+            Stmt::Expression(expr) => Json::tagged("Expression", expr.to_json()),
+            Stmt::Print(expr) => Json::tagged("Print", expr.to_json()),
+            Stmt::Var(var) => Json::tagged("Var", Json::object(vec![
+                ("name", var.name.to_json()),
+                ("initializer", var.initializer.to_json()),
+            ])),
+            Stmt::Block(statements) => Json::tagged("Block", statements.to_json()),
+            Stmt::If(if_stmt) => Json::tagged("If", Json::object(vec![
+                ("condition", if_stmt.condition.to_json()),
+                ("if_branch", if_stmt.if_branch.to_json()),
+                ("else_branch", if_stmt.else_branch.to_json()),
+            ])),
+            Stmt::While(while_stmt) => Json::tagged("While", Json::object(vec![
+                ("label", while_stmt.label.to_json()),
+                ("keyword", while_stmt.keyword.to_json()),
+                ("condition", while_stmt.condition.to_json()),
+                ("body", while_stmt.body.to_json()),
+            ])),
+            Stmt::For(for_stmt) => Json::tagged("For", Json::object(vec![
+                ("label", for_stmt.label.to_json()),
+                ("keyword", for_stmt.keyword.to_json()),
+                ("initializer", for_stmt.initializer.to_json()),
+                ("condition", for_stmt.condition.to_json()),
+                ("increment", for_stmt.increment.to_json()),
+                ("body", for_stmt.body.to_json()),
+            ])),
+            Stmt::ForIn(for_in) => Json::tagged("ForIn", Json::object(vec![
+                ("label", for_in.label.to_json()),
+                ("name", for_in.name.to_json()),
+                ("keyword", for_in.keyword.to_json()),
+                ("iterable", for_in.iterable.to_json()),
+                ("body", for_in.body.to_json()),
+            ])),
+            Stmt::Func(func_stmt) => Json::tagged("Func", func_stmt.to_json()),
+            Stmt::Return(return_stmt) => Json::tagged("Return", Json::object(vec![
+                ("keyword", return_stmt.keyword.to_json()),
+                ("value", return_stmt.value.to_json()),
+            ])),
+            Stmt::Break(jump) => Json::tagged("Break", jump.to_json()),
+            Stmt::Continue(jump) => Json::tagged("Continue", jump.to_json()),
+            Stmt::Throw(throw) => Json::tagged("Throw", Json::object(vec![
+                ("keyword", throw.keyword.to_json()),
+                ("value", throw.value.to_json()),
+            ])),
+            Stmt::Try(try_stmt) => Json::tagged("Try", Json::object(vec![
+                ("keyword", try_stmt.keyword.to_json()),
+                ("body", try_stmt.body.to_json()),
+                ("catch", try_stmt.catch.to_json()),
+                ("finally", try_stmt.finally.to_json()),
+            ])),
+        }
+    }
+}
+
+impl FromJson for Stmt {
+    fn from_json(json: &Json) -> Result<Self, JsonError> {
+        let (tag, payload) = json.variant()?;
+        Ok(match tag {
+            "Expression" => Stmt::Expression(Expr::from_json(payload)?),
+            "Print" => Stmt::Print(Expr::from_json(payload)?),
+            "Var" => Stmt::Var(Variable {
+                name: Token::from_json(payload.field("name")?)?,
+                initializer: Expr::from_json(payload.field("initializer")?)?,
+            }),
+            "Block" => Stmt::Block(Vec::from_json(payload)?),
+            "If" => Stmt::If(IfStmt {
+                condition: Expr::from_json(payload.field("condition")?)?,
+                if_branch: Box::from_json(payload.field("if_branch")?)?,
+                else_branch: Option::from_json(payload.field("else_branch")?)?,
+            }),
+            "While" => Stmt::While(WhileStmt {
+                label: Option::from_json(payload.field("label")?)?,
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                condition: Expr::from_json(payload.field("condition")?)?,
+                body: Box::from_json(payload.field("body")?)?,
+            }),
+            "For" => Stmt::For(ForStmt {
+                label: Option::from_json(payload.field("label")?)?,
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                initializer: Option::from_json(payload.field("initializer")?)?,
+                condition: Option::from_json(payload.field("condition")?)?,
+                increment: Option::from_json(payload.field("increment")?)?,
+                body: Box::from_json(payload.field("body")?)?,
+            }),
+            "ForIn" => Stmt::ForIn(ForInStmt {
+                label: Option::from_json(payload.field("label")?)?,
+                name: Token::from_json(payload.field("name")?)?,
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                iterable: Expr::from_json(payload.field("iterable")?)?,
+                body: Box::from_json(payload.field("body")?)?,
+            }),
+            "Func" => Stmt::Func(FuncStmt::from_json(payload)?),
+            "Return" => Stmt::Return(ReturnStmt {
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                value: Expr::from_json(payload.field("value")?)?,
+            }),
+            "Break" => Stmt::Break(LoopJump::from_json(payload)?),
+            "Continue" => Stmt::Continue(LoopJump::from_json(payload)?),
+            "Throw" => Stmt::Throw(ThrowStmt {
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                value: Expr::from_json(payload.field("value")?)?,
+            }),
+            "Try" => Stmt::Try(TryStmt {
+                keyword: Token::from_json(payload.field("keyword")?)?,
+                body: Vec::from_json(payload.field("body")?)?,
+                catch: Option::from_json(payload.field("catch")?)?,
+                finally: Option::from_json(payload.field("finally")?)?,
+            }),
+            _ => return Err(unknown_variant("Stmt", tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lox::ast::parser::Parser;
+    use crate::lox::ast::printer::{DumpFormat, Printer};
+    use crate::scanner::Scanner;
+
+    use super::{stmts_from_json, stmts_to_json, Json};
+
+    #[test]
+    fn test_json_round_trip() {
+        let source = "var x = 1; fun f(a, b) { print a + -b; }\n\
+                      if (x >= 1 and !false) f(x, \"tw\\o\"); else { var y; }\n\
+                      fun g() { return; } fun h(n) { return n * 2; }\n\
+                      while (x < 3) x = x + 1;\n\
+                      for (var i = 0; i < 2; i = i + 1) print (i * 2.5);\n\
+                      for (;;) {}\n\
+                      var xs = [1, [], x,]; xs[0] = xs[2][1];\n\
+                      var m = {\"k\": xs, 2: {}}; m[nil] = m[2];\n\
+                      for (var k in m) print k; for (var c in \"ab\") { print c; }\n\
+                      outer: while (true) { for (;;) { if (x) continue outer; break; } break outer; }\n\
+                      var sq = (n) => n * n; map(xs, fun (a) { return a; });\n\
+                      x += x > 1 ? 2 ** 3 : 7 % 2; xs[0] *= 2;\n\
+                      print m?.[nil]?.(1) ?? xs[0](2);\n\
+                      try { throw {\"message\": x}; } catch (e) { print e; } finally {} try {} finally { x; }"
+            .to_string();
+        let stmts = Parser::new(Scanner::new(source).scan_tokens()).parse().unwrap();
+        let json = stmts_to_json(&stmts);
+        let back = stmts_from_json(&json).unwrap();
+
+        let mut printer = Printer;
+        assert_eq!(printer.print_stmts(&back, DumpFormat::SExpr), printer.print_stmts(&stmts, DumpFormat::SExpr));
+        // and the JSON itself is stable
+        assert_eq!(stmts_to_json(&back), json);
+    }
+
+    #[test]
+    fn test_json_infinite_numbers() {
+        let huge = format!("1{}", "0".repeat(400));
+        let stmts = Parser::new(Scanner::new(format!("print {huge}; print -{huge};")).scan_tokens()).parse().unwrap();
+        let json = stmts_to_json(&stmts);
+        assert!(json.contains("\"Number\": \"inf\""));
+        assert!(!json.contains("null"));
+        let back = stmts_from_json(&json).unwrap();
+        let mut printer = Printer;
+        assert_eq!(printer.print_stmts(&back, DumpFormat::SExpr), printer.print_stmts(&stmts, DumpFormat::SExpr));
+        assert_eq!(stmts_to_json(&back), json);
+    }
+
+    #[test]
+    fn test_json_values() {
+        let json = Json::parse(" {\"a\": [1, -2.5e1, true, null], \"b\": \"\\u00e9\\n\\ud83d\\ude00\"} ").unwrap();
+        assert_eq!(json.compact(), "{\"a\":[1,-25,true,null],\"b\":\"é\\n😀\"}");
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(stmts_from_json("[{\"Print\": {\"Bogus\": 1}}]").is_err());
+
+        let deep = format!("{}{}", "[".repeat(2_000_000), "]".repeat(2_000_000));
+        assert!(Json::parse(&deep).unwrap_err().message.starts_with("nested too deeply, the limit is 804 levels"));
+        let grouping = format!("[{{\"Print\": {}{{\"Literal\": \"Nil\"}}{}}}]", "{\"Grouping\": ".repeat(100_000), "}".repeat(100_000));
+        assert!(stmts_from_json(&grouping).unwrap_err().message.starts_with("nested too deeply"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::token_type::Value;
+
+use super::interpreter::type_name;
+
+// What's behind a Value::Map. Entries keep the order they were first
+// inserted in, so printing a map or walking its keys gives the same output
+// every run (a bare HashMap would shuffle them).
+#[derive(Debug, Clone, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    // key -> its position in entries
+    positions: HashMap<MapKey, usize>,
+}
+
+// Only strings, numbers, booleans and nil can be keys: they're compared by
+// value, where lists, maps and functions are compared by identity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(String),
+    Number(u64),  // the f64's bits, with -0 folded into 0
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    // the error is the message for a value that can't be a key
+    pub fn from_value(value: &Value) -> Result<MapKey, String> {
+        match value {
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            Value::Number(n) if n.is_nan() => Err("nan can't be a map key".to_string()),
+            Value::Number(n) => Ok(MapKey::Number(if *n == 0.0 { 0f64.to_bits() } else { n.to_bits() })),
+            Value::Boolean(b) => Ok(MapKey::Boolean(*b)),
+            Value::Nil => Ok(MapKey::Nil),
+            _ => Err(format!("a {} can't be a map key", type_name(value))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::String(s) => Value::String(s.clone()),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::Boolean(b) => Value::Boolean(*b),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    // a key that's already there keeps its place
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.positions.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        // everything after it moved down one
+        for position in self.positions.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
@@ -1,44 +1,77 @@
 
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
 
 use crate::token_type::Token;
 use crate::token_type::Literal;
 use crate::token_type::Value;
 
 use environment::Environment;
-use interpreter::{Interp, Result};
+use interpreter::{Interp, Interrupt, Result, RuntimeError};
 use printer::Printer;
 
 pub mod parser;
 pub mod printer;
+pub mod formatter;
+pub mod json;
 pub mod environment;
+pub mod resolver;
+pub mod natives;
+pub mod map;
+pub mod heap;
+pub mod stack;
 // pub mod interpreter_old;
 pub mod interpreter;
 
-#[derive(Debug, Clone)]
+// A `fun` declaration plus the scope it was declared in, so the body can see
+// the variables around it.
+#[derive(Clone)]
 pub struct LoxFunction {
-    declaration: FuncStmt
+    declaration: FuncStmt,
+    closure: Arc<Mutex<Environment>>,
+}
+
+// not derived: the closure usually holds the function itself
+impl std::fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
+
+impl LoxFunction {
+    pub fn new(declaration: FuncStmt, closure: Arc<Mutex<Environment>>) -> Self {
+        Self { declaration, closure }
+    }
 }
 
 impl LoxCallable for LoxFunction {
+    fn name(&self) -> String {
+        self.declaration.name.lexeme.clone()
+    }
+
     fn arity(&self) -> usize {
         self.declaration.parameters.len()
     }
 
-    fn call(&self, mut interpreter: Interp, arguments: Vec<Value>) -> Result<Expr> {
-        let mut environment = Environment::new(None);
-        for i in 0..(arguments.len()-1) {
-            let arg = &arguments[i];
-            let param = &self.declaration.parameters[i];
+    fn closure(&self) -> Option<Arc<Mutex<Environment>>> {
+        Some(Arc::clone(&self.closure))
+    }
+
+    fn call(&self, interpreter: &mut Interp, _paren: &Token, arguments: Vec<Value>) -> Result<Value> {
+        let mut environment = Environment::new(Some(Arc::clone(&self.closure)));
+        for (param, arg) in self.declaration.parameters.iter().zip(&arguments) {
             environment.define(&param.lexeme, arg);
         }
-        let env2 = Arc::new(Mutex::new(environment));
-        interpreter.execute_block(&self.declaration.body, env2);
-        return Ok(Expr::Literal(Literal::Nil))
-
+        match interpreter.execute_block(&self.declaration.body, Arc::new(Mutex::new(environment))) {
+            Ok(()) => Ok(Value::Nil),
+            Err(Interrupt::Return(value)) => Ok(value),
+            Err(Interrupt::Error(err)) => Err(err),
+            // the resolver doesn't let break / continue out of a function
+            // body, but code that hasn't been through it can still try
+            Err(Interrupt::Break(jump) | Interrupt::Continue(jump)) => Err(RuntimeError::new(
+                jump.keyword.clone(), format!("can't use '{}' outside a loop", jump.keyword.lexeme)
+            )),
+        }
     }
 }
 
@@ -50,7 +83,14 @@ pub enum Stmt {
     Block(Vec<Stmt>),
     If(IfStmt),
     While(WhileStmt),
+    For(ForStmt),
+    ForIn(ForInStmt),
     Func(FuncStmt),
+    Return(ReturnStmt),
+    Break(LoopJump),
+    Continue(LoopJump),
+    Throw(ThrowStmt),
+    Try(TryStmt),
 }
 
 #[derive(Debug, Clone)]
@@ -60,12 +100,76 @@ pub struct FuncStmt {
     body: Vec<Stmt>
 }
 
+// `value` is Expr::Null for a bare `return;`, like an uninitialised var
+#[derive(Debug, Clone)]
+pub struct ReturnStmt {
+    keyword: Token,
+    value: Expr,
+}
+
+// throw value;
+#[derive(Debug, Clone)]
+pub struct ThrowStmt {
+    keyword: Token,
+    value: Expr,
+}
+
+// try { body } catch (e) { ... } finally { ... }, with at least one of the
+// catch and the finally
+#[derive(Debug, Clone)]
+pub struct TryStmt {
+    keyword: Token,
+    body: Vec<Stmt>,
+    catch: Option<CatchClause>,
+    finally: Option<Vec<Stmt>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CatchClause {
+    name: Token,
+    body: Vec<Stmt>,
+}
+
+// `break;` / `continue outer;`. With no label it's the innermost loop.
+#[derive(Debug, Clone)]
+pub struct LoopJump {
+    keyword: Token,
+    label: Option<Token>,
+}
+
+// Each loop carries the label it was given (`outer: while ...`), if any, so
+// a labelled break / continue can find it.
 #[derive(Debug, Clone)]
 pub struct WhileStmt {
+    label: Option<Token>,
+    keyword: Token,  // the `while`, for errors about the loop as a whole
     condition: Expr,
     body: Box<Stmt>,
 }
 
+// Kept as its own node rather than desugared into a WhileStmt by the parser,
+// so the formatter can write the `for` back out. Interp still runs it as
+// { initializer; while (condition) { body; increment; } }
+#[derive(Debug, Clone)]
+pub struct ForStmt {
+    label: Option<Token>,
+    keyword: Token,  // the `for`
+    initializer: Option<Box<Stmt>>,
+    condition: Option<Expr>,
+    increment: Option<Expr>,
+    body: Box<Stmt>,
+}
+
+// for (var name in iterable) body. `keyword` is the `in`, where "can't
+// iterate over" errors point.
+#[derive(Debug, Clone)]
+pub struct ForInStmt {
+    label: Option<Token>,
+    name: Token,
+    keyword: Token,
+    iterable: Expr,
+    body: Box<Stmt>,
+}
 
 #[derive(Debug, Clone)]
 pub struct IfStmt {
@@ -85,10 +189,16 @@ pub struct Variable {
 #[derive(Debug, Clone)]
 pub enum Expr {
     Assign(Assign),
+    Conditional(Conditional),
     Logical(Logical),
     Binary(Binary),
     Unary(Unary),
     Call(Call),
+    Lambda(Lambda),
+    List(ListExpr),
+    Map(MapExpr),
+    Index(Index),
+    IndexAssign(IndexAssign),
     Grouping(Grouping),
     // LiteralExpr(LiteralExpr),
     Literal(Literal),
@@ -100,7 +210,55 @@ pub enum Expr {
 pub struct Call {
     callee: Box<Expr>,
     paren: Token,
-    arguments: Vec<Expr>
+    arguments: Vec<Expr>,
+    // the '?.' of `f?.(x)`, which is nil rather than an error when f is nil
+    optional: Option<Token>,
+}
+
+// fun (a, b) { ... } or (a, b) => a + b, a function value with no name. An
+// arrow's body is kept as a lone `return <expr>;` so both kinds run the same
+// way; `arrow` is there so the formatter can write it back out as it was.
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    keyword: Token,  // the `fun` or the `=>`
+    parameters: Vec<Token>,
+    body: Vec<Stmt>,
+    arrow: bool,
+}
+
+// [a, b, c]
+#[derive(Debug, Clone)]
+pub struct ListExpr {
+    bracket: Token,  // the '[', for errors
+    elements: Vec<Expr>,
+}
+
+// {key: value, ...}
+#[derive(Debug, Clone)]
+pub struct MapExpr {
+    brace: Token,  // the '{', for errors
+    entries: Vec<(Expr, Expr)>,
+}
+
+// object[index], a list element or a map entry. `bracket` is the closing
+// ']', like Call's paren, and is where out-of-range / missing key errors point.
+#[derive(Debug, Clone)]
+pub struct Index {
+    object: Box<Expr>,
+    bracket: Token,
+    index: Box<Expr>,
+    // the '?.' of `xs?.[i]`, as for Call
+    optional: Option<Token>,
+}
+
+// object[index] = value, or a compound one like object[index] += value
+#[derive(Debug, Clone)]
+pub struct IndexAssign {
+    object: Box<Expr>,
+    bracket: Token,
+    index: Box<Expr>,
+    operator: Token,  // EQUAL, PLUS_EQUAL, ... (as for Assign)
+    value: Box<Expr>,
 }
 
 // Stmt::Var is for `var x = 4;` etc.,
@@ -113,12 +271,25 @@ pub struct Call {
 #[derive(Debug, Clone)]
 pub struct Assign {
     name: Token,
+    // EQUAL, or one of PLUS_EQUAL / MINUS_EQUAL / STAR_EQUAL / SLASH_EQUAL /
+    // PERCENT_EQUAL for `x += 1` and friends
+    operator: Token,
     value: Box<Expr>
 }
 
+// condition ? then_branch : else_branch. Right associative, so
+// `a ? b : c ? d : e` is `a ? b : (c ? d : e)`.
+#[derive(Debug, Clone)]
+pub struct Conditional {
+    condition: Box<Expr>,
+    question: Token,
+    then_branch: Box<Expr>,
+    else_branch: Box<Expr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Logical {
-    operator: Token,  // actually only AND or OR
+    operator: Token,  // actually only AND, OR or QUESTION_QUESTION (??)
     left: Box<Expr>,
     right: Box<Expr>,
 }
@@ -149,23 +320,19 @@ pub trait ExprVisitor<T> {
     // NOTE: would it be better to make these associated functions without &self?
     // fn visit_expr(&self, expr: &Expr) -> T;
     fn visit_expr(&mut self, expr: &Expr) -> T {
-            match expr {
-            Expr::Assign(assign) => self.visit_assignment(assign),
-            Expr::Logical(logical) => self.visit_logical(logical),
-            Expr::Binary(binary) => self.visit_binary(binary),
-            Expr::Unary(unary) => self.visit_unary(unary),
-            Expr::Call(call) => self.visit_call(call),
-            Expr::Literal(literal) => self.visit_literal(literal),
-            Expr::Grouping(grouping) => self.visit_grouping(grouping),
-            Expr::Variable(token) => self.visit_variable(token),
-            Expr::Null => self.visit_null(),
-        }
+        walk_expr(self, expr)
     }
     fn visit_assignment(&mut self, assignment: &Assign) -> T;
+    fn visit_conditional(&mut self, conditional: &Conditional) -> T;
     fn visit_logical(&mut self, logical: &Logical) -> T;
     fn visit_binary(&mut self, binary: &Binary) -> T;
     fn visit_unary(&mut self, unary: &Unary) -> T;
     fn visit_call(&mut self, call: &Call) -> T;
+    fn visit_lambda(&mut self, lambda: &Lambda) -> T;
+    fn visit_list(&mut self, list: &ListExpr) -> T;
+    fn visit_map(&mut self, map: &MapExpr) -> T;
+    fn visit_index(&mut self, index: &Index) -> T;
+    fn visit_index_assign(&mut self, index_assign: &IndexAssign) -> T;
     fn visit_literal(&mut self, literal: &Literal) -> T;
     fn visit_grouping(&mut self, grouping: &Grouping) -> T;
     fn visit_variable(&mut self, token: &Token) -> T;
@@ -175,49 +342,81 @@ pub trait ExprVisitor<T> {
 // wtf?? unclear if we actually need this??
 pub trait StmtVisitor<T> {
     fn visit_statement(&mut self, stmt: &Stmt) -> T {
-        match stmt {
-            Stmt::Expression(expr) => self.visit_expr_statement(expr),
-            Stmt::Print(expr) => self.visit_print_statement(expr),
-            Stmt::Block(statements) => self.visit_block_statement(statements),
-            Stmt::While(while_stmt) => self.visit_while_statement(while_stmt),
-            Stmt::Func(func_stmt) => self.visit_func_statement(func_stmt),
-            Stmt::Var(var) => self.visit_var_statement(var),
-            Stmt::If(if_stmt) => self.visit_if_statement(if_stmt)
-        }
+        walk_statement(self, stmt)
     }
     fn visit_expr_statement(&mut self, expr: &Expr) -> T;
     fn visit_print_statement(&mut self, expr: &Expr) -> T;
     fn visit_block_statement(&mut self, statements: &Vec<Stmt>) -> T;
     fn visit_while_statement(&mut self, while_stmt: &WhileStmt) -> T;
+    fn visit_for_statement(&mut self, for_stmt: &ForStmt) -> T;
+    fn visit_for_in_statement(&mut self, for_in: &ForInStmt) -> T;
     fn visit_func_statement(&mut self, func_stmt: &FuncStmt) -> T;
     fn visit_var_statement(&mut self, var: &Variable) -> T;
     fn visit_if_statement(&mut self, if_stmt: &IfStmt) -> T;
+    fn visit_return_statement(&mut self, return_stmt: &ReturnStmt) -> T;
+    fn visit_break_statement(&mut self, jump: &LoopJump) -> T;
+    fn visit_continue_statement(&mut self, jump: &LoopJump) -> T;
+    fn visit_throw_statement(&mut self, throw: &ThrowStmt) -> T;
+    fn visit_try_statement(&mut self, try_stmt: &TryStmt) -> T;
 }
 
-pub trait LoxCallable {
-    fn call(&self, interpreter: Interp, arguments: Vec<Value>) -> Result<Expr>;
-    fn arity(&self) -> usize;
+// The dispatch behind visit_expr, pulled out like walk_statement below
+// (Interp counts steps in its own visit_expr).
+pub fn walk_expr<T, V: ExprVisitor<T> + ?Sized>(visitor: &mut V, expr: &Expr) -> T {
+    match expr {
+        Expr::Assign(assign) => visitor.visit_assignment(assign),
+        Expr::Conditional(conditional) => visitor.visit_conditional(conditional),
+        Expr::Logical(logical) => visitor.visit_logical(logical),
+        Expr::Binary(binary) => visitor.visit_binary(binary),
+        Expr::Unary(unary) => visitor.visit_unary(unary),
+        Expr::Call(call) => visitor.visit_call(call),
+        Expr::Lambda(lambda) => visitor.visit_lambda(lambda),
+        Expr::List(list) => visitor.visit_list(list),
+        Expr::Map(map) => visitor.visit_map(map),
+        Expr::Index(index) => visitor.visit_index(index),
+        Expr::IndexAssign(index_assign) => visitor.visit_index_assign(index_assign),
+        Expr::Literal(literal) => visitor.visit_literal(literal),
+        Expr::Grouping(grouping) => visitor.visit_grouping(grouping),
+        Expr::Variable(token) => visitor.visit_variable(token),
+        Expr::Null => visitor.visit_null(),
+    }
 }
 
-pub struct NullFunc;
-
-impl LoxCallable for NullFunc{
-    fn arity(&self) -> usize{ 0}
-    fn call(&self, interpreter: Interp, arguments: Vec<Value>) -> Result<Expr>{
-        Ok(Expr::Literal(Literal::Nil))
+// The dispatch behind visit_statement, pulled out so a visitor that overrides
+// visit_statement (Interp, for tracing) can still get at it.
+pub fn walk_statement<T, V: StmtVisitor<T> + ?Sized>(visitor: &mut V, stmt: &Stmt) -> T {
+    match stmt {
+        Stmt::Expression(expr) => visitor.visit_expr_statement(expr),
+        Stmt::Print(expr) => visitor.visit_print_statement(expr),
+        Stmt::Block(statements) => visitor.visit_block_statement(statements),
+        Stmt::While(while_stmt) => visitor.visit_while_statement(while_stmt),
+        Stmt::For(for_stmt) => visitor.visit_for_statement(for_stmt),
+        Stmt::ForIn(for_in) => visitor.visit_for_in_statement(for_in),
+        Stmt::Func(func_stmt) => visitor.visit_func_statement(func_stmt),
+        Stmt::Var(var) => visitor.visit_var_statement(var),
+        Stmt::If(if_stmt) => visitor.visit_if_statement(if_stmt),
+        Stmt::Return(return_stmt) => visitor.visit_return_statement(return_stmt),
+        Stmt::Break(jump) => visitor.visit_break_statement(jump),
+        Stmt::Continue(jump) => visitor.visit_continue_statement(jump),
+        Stmt::Throw(throw) => visitor.visit_throw_statement(throw),
+        Stmt::Try(try_stmt) => visitor.visit_try_statement(try_stmt),
     }
 }
 
-pub struct Clock;
-impl LoxCallable for Clock{
-    fn arity(&self) -> usize{ 0}
-    fn call(&self, interpreter: Interp, arguments: Vec<Value>) -> Result<Expr>{
-        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
-        Ok(Expr::Literal(Literal::Number(time)))
-    }
+// Anything that can sit in a Value::Callable. `paren` is the call's closing
+// paren, for errors that belong to the call as a whole.
+pub trait LoxCallable: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> String;
+    fn arity(&self) -> usize;
+    fn call(&self, interpreter: &mut Interp, paren: &Token, arguments: Vec<Value>) -> Result<Value>;
+    // natives print as <native fn len>, Lox functions as <fn f>
+    fn is_native(&self) -> bool { false }
+    // the scope a Lox function closes over, for counting what's reachable
+    fn closure(&self) -> Option<Arc<Mutex<Environment>>> { None }
 }
 
 
+
 // pub struct Printer;
 // impl Printer {
 //     pub fn print(&self, expr: &Expr) -> String {
@@ -0,0 +1,1167 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::token_type::{Token, Value};
+
+use super::environment::Environment;
+use super::heap;
+use super::map::{LoxMap, MapKey};
+use super::interpreter::{is_truthy, stringify, type_name, Interp, Result, RuntimeError};
+use super::LoxCallable;
+
+// Functions built into the interpreter. Interp::new defines them all in its
+// globals, so a script can shadow any of them with its own.
+
+type NativeFn = fn(&mut Interp, &Args) -> Result<Value>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    function: NativeFn,
+}
+
+impl LoxCallable for NativeFunction {
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interp, paren: &Token, arguments: Vec<Value>) -> Result<Value> {
+        (self.function)(interpreter, &Args { name: self.name, paren, values: arguments })
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+const fn native(name: &'static str, arity: usize, function: NativeFn) -> NativeFunction {
+    NativeFunction { name, arity, function }
+}
+
+// All the string functions count in Unicode scalar values (chars), not bytes,
+// so `len("héllo")` is 5 and indices never land inside a character.
+const STRING_NATIVES: &[NativeFunction] = &[
+    native("len", 1, len),
+    native("substr", 3, substr),
+    native("indexOf", 2, index_of),
+    native("split", 2, split),
+    native("join", 2, join),
+    native("upper", 1, upper),
+    native("lower", 1, lower),
+    native("trim", 1, trim),
+    native("replace", 3, replace),
+    native("startsWith", 2, starts_with),
+    native("endsWith", 2, ends_with),
+    native("charAt", 2, char_at),
+    native("ord", 1, ord),
+    native("chr", 1, chr),
+];
+
+const MATH_NATIVES: &[NativeFunction] = &[
+    native("sqrt", 1, sqrt),
+    native("pow", 2, pow),
+    native("floor", 1, floor),
+    native("ceil", 1, ceil),
+    native("round", 1, round),
+    native("abs", 1, abs),
+    native("min", 2, min),
+    native("max", 2, max),
+    native("sin", 1, sin),
+    native("cos", 1, cos),
+    native("tan", 1, tan),
+    native("atan2", 2, atan2),
+    native("log", 1, log),
+    native("exp", 1, exp),
+    native("isNaN", 1, is_nan),
+    native("isInfinite", 1, is_infinite),
+    native("parseNumber", 1, parse_number),
+    native("toFixed", 2, to_fixed),
+];
+
+// Lists are shared, not copied, so push / pop / insert / remove change the
+// list every other reference sees too. slice, map and filter build a new one.
+// (len is with the string natives, it takes strings, lists and maps.)
+const LIST_NATIVES: &[NativeFunction] = &[
+    native("push", 2, push),
+    native("pop", 1, pop),
+    native("insert", 3, insert),
+    native("remove", 2, remove),
+    native("slice", 3, slice),
+    native("map", 2, map),
+    native("filter", 2, filter),
+    native("reduce", 3, reduce),
+    native("range", 2, range),
+];
+
+// Maps are shared the same way. keys and values come back in insertion order.
+const MAP_NATIVES: &[NativeFunction] = &[
+    native("keys", 1, keys),
+    native("values", 1, values),
+    native("has", 2, has),
+    native("delete", 2, delete),
+];
+
+// Anything that reaches outside the interpreter. Each checks Interp's
+// Permissions first.
+const IO_NATIVES: &[NativeFunction] = &[
+    native("readFile", 1, read_file),
+    native("writeFile", 2, write_file),
+    native("appendFile", 2, append_file),
+    native("fileExists", 1, file_exists),
+    native("listDir", 1, list_dir),
+    native("readLine", 0, read_line),
+    native("getEnv", 1, get_env),
+    native("args", 0, args),
+    native("exit", 1, exit),
+];
+
+// All read Interp's TimeSource, so a test can swap in a FakeClock.
+const TIME_NATIVES: &[NativeFunction] = &[
+    native("clock", 0, clock),
+    native("now", 0, now),
+    native("sleep", 1, sleep),
+];
+
+// Drawn from Interp's Rng, so seed() (or --seed) makes a run repeatable.
+const RANDOM_NATIVES: &[NativeFunction] = &[
+    native("random", 0, random),
+    native("randomInt", 2, random_int),
+    native("shuffle", 1, shuffle),
+    native("seed", 1, seed),
+];
+
+const MATH_CONSTANTS: &[(&str, f64)] = &[
+    ("PI", std::f64::consts::PI),
+    ("E", std::f64::consts::E),
+];
+
+pub fn define_natives(globals: &mut Environment) {
+    for native in STRING_NATIVES.iter().chain(LIST_NATIVES).chain(MAP_NATIVES).chain(MATH_NATIVES).chain(IO_NATIVES).chain(TIME_NATIVES).chain(RANDOM_NATIVES) {
+        globals.define(&native.name.to_string(), &Value::Callable(Arc::new(*native)));
+    }
+    for (name, value) in MATH_CONSTANTS {
+        globals.define(&name.to_string(), &Value::Number(*value));
+    }
+}
+
+// What the I/O natives may do. Everything is allowed by default; a host that
+// runs untrusted scripts hands Interp::set_permissions something stricter,
+// e.g. Permissions::none().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Permissions {
+    pub read_files: bool,   // readFile, fileExists, listDir
+    pub write_files: bool,  // writeFile, appendFile
+    pub env: bool,          // getEnv
+    pub input: bool,        // readLine, args - what the host process was given
+    pub exit: bool,         // exit() ends the whole process, not just the script
+}
+
+impl Permissions {
+    pub fn all() -> Self {
+        Self { read_files: true, write_files: true, env: true, input: true, exit: true }
+    }
+
+    pub fn none() -> Self {
+        Self { read_files: false, write_files: false, env: false, input: false, exit: false }
+    }
+}
+
+// Where clock(), now() and sleep() get their time from.
+pub trait TimeSource: Send {
+    // milliseconds since the Unix epoch
+    fn now_ms(&self) -> f64;
+    fn sleep(&mut self, ms: f64);
+}
+
+// the real thing
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now_ms(&self) -> f64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64() * 1000.0
+    }
+
+    fn sleep(&mut self, ms: f64) {
+        // more than a Duration holds is as good as forever
+        std::thread::sleep(Duration::try_from_secs_f64(ms / 1000.0).unwrap_or(Duration::MAX));
+    }
+}
+
+// Stands still until slept on, then jumps ahead by exactly that much without
+// waiting, so scripts that time things give the same output every run.
+#[cfg(test)]
+pub struct FakeClock {
+    now_ms: f64,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(start_ms: f64) -> Self {
+        Self { now_ms: start_ms }
+    }
+}
+
+#[cfg(test)]
+impl TimeSource for FakeClock {
+    fn now_ms(&self) -> f64 {
+        self.now_ms
+    }
+
+    fn sleep(&mut self, ms: f64) {
+        self.now_ms += ms;
+    }
+}
+
+// SplitMix64: tiny, fast and plenty random for games and simulations (not
+// for anything secret). The same seed always gives the same sequence.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // seeded from the clock, for when nobody asked for a particular seed
+    pub fn from_time() -> Self {
+        Self::new(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // in [0, 1), from the top 53 bits so every value is equally likely
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // in [0, bound), without the bias a plain `% bound` would have
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let n = self.next_u64();
+            if n < zone {
+                return n % bound;
+            }
+        }
+    }
+}
+
+// A native's arguments, with checked accessors so each native doesn't have
+// to spell out its own type errors. Errors point at the call's ')'.
+pub struct Args<'a> {
+    name: &'static str,
+    paren: &'a Token,
+    values: Vec<Value>,
+}
+
+impl Args<'_> {
+    fn error(&self, message: String) -> RuntimeError {
+        RuntimeError::new(self.paren.clone(), format!("{}: {message}", self.name))
+    }
+
+    fn wrong_type(&self, index: usize, expected: &str) -> RuntimeError {
+        self.error(format!("argument {} should be a {expected}, not a {}", index + 1, type_name(&self.values[index])))
+    }
+
+    fn get(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+
+    fn string(&self, index: usize) -> Result<&str> {
+        match &self.values[index] {
+            Value::String(s) => Ok(s),
+            _ => Err(self.wrong_type(index, "string")),
+        }
+    }
+
+    fn number(&self, index: usize) -> Result<f64> {
+        match self.values[index] {
+            Value::Number(n) => Ok(n),
+            _ => Err(self.wrong_type(index, "number")),
+        }
+    }
+
+    // a whole, non-negative number
+    fn index(&self, index: usize) -> Result<usize> {
+        let n = self.number(index)?;
+        if n.fract() != 0.0 || n < 0.0 || n > u32::MAX as f64 {
+            return Err(self.error(format!("{} isn't a valid index", stringify(&Value::Number(n)))));
+        }
+        Ok(n as usize)
+    }
+
+    fn list(&self, index: usize) -> Result<Arc<Mutex<Vec<Value>>>> {
+        match &self.values[index] {
+            Value::List(list) => Ok(Arc::clone(list)),
+            _ => Err(self.wrong_type(index, "list")),
+        }
+    }
+
+    fn map(&self, index: usize) -> Result<Arc<Mutex<LoxMap>>> {
+        match &self.values[index] {
+            Value::Map(map) => Ok(Arc::clone(map)),
+            _ => Err(self.wrong_type(index, "map")),
+        }
+    }
+
+    fn key(&self, index: usize) -> Result<MapKey> {
+        MapKey::from_value(&self.values[index]).map_err(|message| self.error(message))
+    }
+
+    fn callable(&self, index: usize) -> Result<Arc<dyn LoxCallable>> {
+        match &self.values[index] {
+            Value::Callable(callable) => Ok(Arc::clone(callable)),
+            _ => Err(self.wrong_type(index, "function")),
+        }
+    }
+
+    // Calls a function argument back. The arity is checked here rather than
+    // left to the callee so the error names the native it was passed to.
+    fn call_back(&self, interp: &mut Interp, function: &Arc<dyn LoxCallable>, arguments: Vec<Value>) -> Result<Value> {
+        if function.arity() != arguments.len() {
+            return Err(self.error(format!(
+                "the function should take {} argument{}, {} takes {}",
+                arguments.len(), if arguments.len() == 1 { "" } else { "s" }, function.name(), function.arity()
+            )));
+        }
+        interp.call_function(function, self.paren, arguments)
+    }
+
+    fn check(&self, allowed: bool, what: &str) -> Result<()> {
+        if allowed {
+            Ok(())
+        } else {
+            Err(self.error(format!("{what} isn't allowed here")))
+        }
+    }
+
+    // see Interp::charge
+    fn charge(&self, interp: &mut Interp, bytes: usize) -> Result<()> {
+        interp.charge(bytes, Some(self.paren))
+    }
+
+    // a list the native has just made, charged for
+    fn new_list(&self, interp: &mut Interp, items: Vec<Value>) -> Result<Value> {
+        self.charge(interp, heap::list_size(&items))?;
+        Ok(Value::List(Arc::new(Mutex::new(items))))
+    }
+
+    fn io_error(&self, path: &str, err: std::io::Error) -> RuntimeError {
+        self.error(format!("{path}: {err}"))
+    }
+
+    // `what` is "string" or "list"
+    fn out_of_range(&self, index: usize, len: usize, what: &str) -> RuntimeError {
+        self.error(format!("index {index} is out of range for a {what} of length {len}"))
+    }
+}
+
+fn len(_: &mut Interp, args: &Args) -> Result<Value> {
+    match args.get(0) {
+        Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+        Value::List(list) => Ok(Value::Number(list.lock().unwrap().len() as f64)),
+        Value::Map(map) => Ok(Value::Number(map.lock().unwrap().len() as f64)),
+        Value::Range(start, end) => Ok(Value::Number(range_len(*start, *end) as f64)),
+        _ => Err(args.wrong_type(0, "string, list, map or range")),
+    }
+}
+
+// substr(s, start, length)
+fn substr(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let chars: Vec<char> = args.string(0)?.chars().collect();
+    let start = args.index(1)?;
+    let length = args.index(2)?;
+    if start > chars.len() {
+        return Err(args.out_of_range(start, chars.len(), "string"));
+    }
+    if start + length > chars.len() {
+        return Err(args.out_of_range(start + length, chars.len(), "string"));
+    }
+    let chars = &chars[start..start + length];
+    args.charge(interp, heap::string_size(chars.iter().map(|c| c.len_utf8()).sum()))?;
+    Ok(Value::String(chars.iter().collect()))
+}
+
+// the char index of the first match, or -1
+fn index_of(_: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?;
+    let found = s.find(args.string(1)?).map(|byte| s[..byte].chars().count() as f64);
+    Ok(Value::Number(found.unwrap_or(-1.0)))
+}
+
+// split(s, "") gives the characters
+fn split(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?;
+    let separator = args.string(1)?;
+    let parts: Vec<Value> = if separator.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(separator).map(|part| Value::String(part.to_string())).collect()
+    };
+    args.new_list(interp, parts)
+}
+
+// strings go in as they are, anything else the way print would show it
+fn join(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let items = items(interp, args)?;
+    let separator = args.string(1)?;
+    let parts: Vec<String> = items.iter()
+        .map(|item| match item {
+            Value::String(s) => s.clone(),
+            other => stringify(other),
+        })
+        .collect();
+    // charged before it's built, a long separator makes it far bigger than the list
+    let length = parts.iter().map(String::len).fold(0, usize::saturating_add)
+        .saturating_add(parts.len().saturating_sub(1).saturating_mul(separator.len()));
+    args.charge(interp, heap::string_size(length))?;
+    Ok(Value::String(parts.join(separator)))
+}
+
+// changing case can change a character's length, e.g. "ß" becomes "SS"
+fn upper(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?;
+    args.charge(interp, heap::string_size(s.chars().flat_map(char::to_uppercase).map(char::len_utf8).sum()))?;
+    Ok(Value::String(s.to_uppercase()))
+}
+
+fn lower(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?;
+    args.charge(interp, heap::string_size(s.chars().flat_map(char::to_lowercase).map(char::len_utf8).sum()))?;
+    Ok(Value::String(s.to_lowercase()))
+}
+
+fn trim(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let trimmed = args.string(0)?.trim();
+    args.charge(interp, heap::string_size(trimmed.len()))?;
+    Ok(Value::String(trimmed.to_string()))
+}
+
+// every occurrence
+fn replace(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let from = args.string(1)?;
+    if from.is_empty() {
+        return Err(args.error("can't replace an empty string".to_string()));
+    }
+    let (s, to) = (args.string(0)?, args.string(2)?);
+    // charged before it's built, it can be far bigger than s
+    let count = s.matches(from).count();
+    args.charge(interp, heap::string_size((s.len() - count * from.len()).saturating_add(count.saturating_mul(to.len()))))?;
+    Ok(Value::String(s.replace(from, to)))
+}
+
+fn starts_with(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Boolean(args.string(0)?.starts_with(args.string(1)?)))
+}
+
+fn ends_with(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Boolean(args.string(0)?.ends_with(args.string(1)?)))
+}
+
+fn char_at(_: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?;
+    let index = args.index(1)?;
+    match s.chars().nth(index) {
+        Some(c) => Ok(Value::String(c.to_string())),
+        None => Err(args.out_of_range(index, s.chars().count(), "string")),
+    }
+}
+
+// the code point of a one-character string
+fn ord(_: &mut Interp, args: &Args) -> Result<Value> {
+    let mut chars = args.string(0)?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::Number(c as u32 as f64)),
+        _ => Err(args.error("expected a string of exactly one character".to_string())),
+    }
+}
+
+fn chr(_: &mut Interp, args: &Args) -> Result<Value> {
+    let code = args.number(0)?;
+    let c = if code.fract() == 0.0 && code >= 0.0 && code <= u32::MAX as f64 {
+        char::from_u32(code as u32)
+    } else {
+        None
+    };
+    match c {
+        Some(c) => Ok(Value::String(c.to_string())),
+        None => Err(args.error(format!("{} isn't a Unicode scalar value", stringify(&Value::Number(code))))),
+    }
+}
+
+fn push(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let list = args.list(0)?;
+    args.charge(interp, heap::clone_size(args.get(1)))?;
+    list.lock().unwrap().push(args.get(1).clone());
+    Ok(Value::Nil)
+}
+
+// removes and returns the last item
+fn pop(_: &mut Interp, args: &Args) -> Result<Value> {
+    match args.list(0)?.lock().unwrap().pop() {
+        Some(item) => Ok(item),
+        None => Err(args.error("can't pop from an empty list".to_string())),
+    }
+}
+
+// insert(list, i, x) puts x before the item at i, or at the end when i is len
+fn insert(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let list = args.list(0)?;
+    let index = args.index(1)?;
+    args.charge(interp, heap::clone_size(args.get(2)))?;
+    let mut items = list.lock().unwrap();
+    if index > items.len() {
+        return Err(args.out_of_range(index, items.len(), "list"));
+    }
+    items.insert(index, args.get(2).clone());
+    Ok(Value::Nil)
+}
+
+// removes and returns the item at i
+fn remove(_: &mut Interp, args: &Args) -> Result<Value> {
+    let list = args.list(0)?;
+    let index = args.index(1)?;
+    let mut items = list.lock().unwrap();
+    if index >= items.len() {
+        return Err(args.out_of_range(index, items.len(), "list"));
+    }
+    Ok(items.remove(index))
+}
+
+// slice(list, start, end), end not included
+fn slice(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let list = args.list(0)?;
+    let start = args.index(1)?;
+    let end = args.index(2)?;
+    let items = list.lock().unwrap();
+    if end > items.len() {
+        return Err(args.out_of_range(end, items.len(), "list"));
+    }
+    if start > end {
+        return Err(args.error(format!("start {start} is after end {end}")));
+    }
+    let sliced = items[start..end].to_vec();
+    drop(items);
+    args.new_list(interp, sliced)
+}
+
+// join, map, filter and reduce work on a copy of the items taken up front,
+// so a callback is free to look at (or change) the list without
+// deadlocking. A range is made into a list for them, charged for first:
+// range(0, 1e15) would take petabytes.
+fn items(interp: &mut Interp, args: &Args) -> Result<Vec<Value>> {
+    let Value::Range(start, end) = *args.get(0) else {
+        return Ok(args.list(0)?.lock().unwrap().clone());
+    };
+    let count = range_len(start, end);
+    args.charge(interp, heap::COLLECTION_BYTES.saturating_add(count.saturating_mul(std::mem::size_of::<Value>())))?;
+    // and without a memory limit it'd abort the whole process
+    if count > MAX_RANGE {
+        return Err(args.error(format!("that's {count} numbers, a list of them can hold at most {MAX_RANGE}")));
+    }
+    Ok((0..count).map(|i| Value::Number(start + i as f64)).collect())
+}
+
+// The results so far are held (see Interp::collect_held), so a callback
+// that makes big values runs into the memory limit part way through.
+fn map(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let function = args.callable(1)?;
+    let items = items(interp, args)?;
+    let mapped = interp.collect_held(|interp| {
+        for item in items {
+            let result = args.call_back(interp, &function, vec![item])?;
+            interp.hold(result);
+        }
+        Ok(())
+    })?;
+    args.new_list(interp, mapped)
+}
+
+// keeps the items the function gives a truthy value for
+fn filter(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let function = args.callable(1)?;
+    let items = items(interp, args)?;
+    let kept = interp.collect_held(|interp| {
+        for item in items {
+            if is_truthy(&args.call_back(interp, &function, vec![item.clone()])?) {
+                interp.hold(item);
+            }
+        }
+        Ok(())
+    })?;
+    args.new_list(interp, kept)
+}
+
+// reduce(list, f, initial) calls f(acc, item) for each item in turn
+fn reduce(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let function = args.callable(1)?;
+    let mut acc = args.get(2).clone();
+    for item in items(interp, args)? {
+        acc = args.call_back(interp, &function, vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+// range(start, end) is start, start + 1, ... up to but not including end,
+// mostly for `for (var i in range(0, n))`. It's a Value::Range rather than a
+// list, so a for-in counts the numbers out one at a time however the range
+// got there; only join, map, filter and reduce make it into a list (see
+// items).
+fn range(_: &mut Interp, args: &Args) -> Result<Value> {
+    let start = args.number(0)?;
+    let end = args.number(1)?;
+    // past 2^53 adding 1 stops changing the number
+    if !(start.abs() <= MAX_SAFE_INTEGER && end.abs() <= MAX_SAFE_INTEGER) {
+        return Err(args.error("the bounds should be between -2^53 and 2^53".to_string()));
+    }
+    Ok(Value::Range(start, end))
+}
+
+pub const MAX_RANGE: usize = 1 << 24;
+
+// how many numbers range(start, end) counts out
+pub fn range_len(start: f64, end: f64) -> usize {
+    (end - start).ceil().max(0.0) as usize
+}
+
+fn keys(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let keys = args.map(0)?.lock().unwrap().iter().map(|(key, _)| key.to_value()).collect();
+    args.new_list(interp, keys)
+}
+
+fn values(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let values = args.map(0)?.lock().unwrap().iter().map(|(_, value)| value.clone()).collect();
+    args.new_list(interp, values)
+}
+
+fn has(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Boolean(args.map(0)?.lock().unwrap().contains(&args.key(1)?)))
+}
+
+// the value that was there, or nil if the key wasn't
+fn delete(_: &mut Interp, args: &Args) -> Result<Value> {
+    let removed = args.map(0)?.lock().unwrap().remove(&args.key(1)?);
+    Ok(removed.unwrap_or(Value::Nil))
+}
+
+fn sqrt(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.sqrt()))
+}
+
+fn pow(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.powf(args.number(1)?)))
+}
+
+fn floor(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.floor()))
+}
+
+fn ceil(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.ceil()))
+}
+
+// halves go away from zero: round(2.5) is 3, round(-2.5) is -3
+fn round(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.round()))
+}
+
+fn abs(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.abs()))
+}
+
+fn min(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.min(args.number(1)?)))
+}
+
+fn max(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.max(args.number(1)?)))
+}
+
+fn sin(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.sin()))
+}
+
+fn cos(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.cos()))
+}
+
+fn tan(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.tan()))
+}
+
+// atan2(y, x)
+fn atan2(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.atan2(args.number(1)?)))
+}
+
+// natural log
+fn log(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.ln()))
+}
+
+fn exp(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Number(args.number(0)?.exp()))
+}
+
+fn is_nan(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Boolean(args.number(0)?.is_nan()))
+}
+
+fn is_infinite(_: &mut Interp, args: &Args) -> Result<Value> {
+    Ok(Value::Boolean(args.number(0)?.is_infinite()))
+}
+
+// nil if the string isn't a number. Only plain decimal notation (with an
+// optional sign and exponent) counts, not "inf" or "nan".
+fn parse_number(_: &mut Interp, args: &Args) -> Result<Value> {
+    let s = args.string(0)?.trim();
+    let plain = s.chars().any(|c| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    match s.parse::<f64>() {
+        Ok(n) if plain => Ok(Value::Number(n)),
+        _ => Ok(Value::Nil),
+    }
+}
+
+// toFixed(n, digits) -> n as a string with exactly `digits` decimals
+fn to_fixed(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let n = args.number(0)?;
+    let digits = args.index(1)?;
+    if digits > 100 {
+        return Err(args.error(format!("can't show {digits} digits, 100 at most")));
+    }
+    let fixed = if n.is_finite() { format!("{n:.digits$}") } else { stringify(&Value::Number(n)) };
+    // a few hundred bytes at most, but it all counts
+    args.charge(interp, heap::string_size(fixed.len()))?;
+    Ok(Value::String(fixed))
+}
+
+fn read_file(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().read_files, "reading files")?;
+    let path = args.string(0)?;
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            args.charge(interp, heap::string_size(contents.len()))?;
+            Ok(Value::String(contents))
+        }
+        Err(err) => Err(args.io_error(path, err)),
+    }
+}
+
+// replaces whatever was there
+fn write_file(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().write_files, "writing files")?;
+    let path = args.string(0)?;
+    match std::fs::write(path, args.string(1)?) {
+        Ok(()) => Ok(Value::Nil),
+        Err(err) => Err(args.io_error(path, err)),
+    }
+}
+
+fn append_file(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().write_files, "writing files")?;
+    let path = args.string(0)?;
+    let contents = args.string(1)?;
+    let result = std::fs::OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(()) => Ok(Value::Nil),
+        Err(err) => Err(args.io_error(path, err)),
+    }
+}
+
+fn file_exists(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().read_files, "reading files")?;
+    Ok(Value::Boolean(std::path::Path::new(args.string(0)?).exists()))
+}
+
+// the names in a directory, sorted
+fn list_dir(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().read_files, "reading files")?;
+    let path = args.string(0)?;
+    let entries = std::fs::read_dir(path)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<std::io::Result<Vec<_>>>());
+    match entries {
+        Ok(names) => {
+            let mut names: Vec<String> = names.into_iter().map(|name| name.to_string_lossy().into_owned()).collect();
+            names.sort();
+            args.new_list(interp, names.into_iter().map(Value::String).collect())
+        }
+        Err(err) => Err(args.io_error(path, err)),
+    }
+}
+
+// nil once the input runs out
+fn read_line(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().input, "reading input")?;
+    match interp.read_line() {
+        Ok(Some(line)) => {
+            args.charge(interp, heap::string_size(line.len()))?;
+            Ok(Value::String(line))
+        }
+        Ok(None) => Ok(Value::Nil),
+        Err(err) => Err(args.error(err.to_string())),
+    }
+}
+
+// nil if it isn't set
+fn get_env(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().env, "reading the environment")?;
+    match std::env::var(args.string(0)?) {
+        Ok(value) => Ok(Value::String(value)),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+fn args(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().input, "reading arguments")?;
+    let list = interp.args().iter().map(|arg| Value::String(arg.clone())).collect();
+    args.new_list(interp, list)
+}
+
+fn exit(interp: &mut Interp, args: &Args) -> Result<Value> {
+    args.check(interp.permissions().exit, "exiting")?;
+    let code = args.number(0)?;
+    if code.fract() != 0.0 || code < i32::MIN as f64 || code > i32::MAX as f64 {
+        return Err(args.error(format!("{} isn't a valid exit code", stringify(&Value::Number(code)))));
+    }
+    let _ = std::io::stdout().flush();
+    std::process::exit(code as i32);
+}
+
+// seconds since the epoch, with a fraction
+fn clock(interp: &mut Interp, _: &Args) -> Result<Value> {
+    Ok(Value::Number(interp.time_source().now_ms() / 1000.0))
+}
+
+// whole milliseconds since the epoch
+fn now(interp: &mut Interp, _: &Args) -> Result<Value> {
+    Ok(Value::Number(interp.time_source().now_ms().floor()))
+}
+
+fn sleep(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let ms = args.number(0)?;
+    if !(ms >= 0.0 && ms.is_finite()) {
+        return Err(args.error(format!("can't sleep for {} ms", stringify(&Value::Number(ms)))));
+    }
+    // not past the deadline, if there is one
+    let ms = interp.time_left_ms().map_or(ms, |left| ms.min(left));
+    interp.time_source().sleep(ms);
+    interp.check_deadline(Some(args.paren))?;
+    Ok(Value::Nil)
+}
+
+fn random(interp: &mut Interp, _: &Args) -> Result<Value> {
+    Ok(Value::Number(interp.rng().next_f64()))
+}
+
+// randomInt(lo, hi), both ends included
+fn random_int(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let lo = args.number(0)?;
+    let hi = args.number(1)?;
+    let whole = |n: f64| n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER;
+    if !whole(lo) || !whole(hi) {
+        return Err(args.error("the bounds should be whole numbers".to_string()));
+    }
+    if lo > hi {
+        return Err(args.error(format!("empty range, {} is above {}", stringify(&Value::Number(lo)), stringify(&Value::Number(hi)))));
+    }
+    let span = (hi - lo) as u64 + 1;
+    Ok(Value::Number(lo + interp.rng().below(span) as f64))
+}
+
+// in place (Fisher-Yates)
+fn shuffle(interp: &mut Interp, args: &Args) -> Result<Value> {
+    let list = args.list(0)?;
+    let mut items = list.lock().unwrap();
+    for i in (1..items.len()).rev() {
+        let j = interp.rng().below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+    Ok(Value::Nil)
+}
+
+// any number will do, the same one gives the same sequence
+fn seed(interp: &mut Interp, args: &Args) -> Result<Value> {
+    interp.set_seed(args.number(0)?.to_bits());
+    Ok(Value::Nil)
+}
+
+// the largest whole number an f64 holds exactly (2^53 - 1)
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::lox::ast::interpreter::{stringify, Interp};
+    use crate::lox::ast::parser::Parser;
+    use crate::scanner::Scanner;
+
+    use super::{FakeClock, Permissions};
+
+    // the value of one expression, or the runtime error's message
+    fn eval(source: &str) -> String {
+        eval_in(&mut Interp::new(), source)
+    }
+
+    fn eval_in(interp: &mut Interp, source: &str) -> String {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let expr = Parser::new(tokens).parse_lone_expr().unwrap();
+        match interp.evaluate(&expr) {
+            Ok(value) => stringify(&value),
+            Err(err) => format!("error: {}", err.message),
+        }
+    }
+
+    #[test]
+    fn test_string_natives() {
+        assert_eq!(eval("len(\"héllo\")"), "5");
+        assert_eq!(eval("substr(\"héllo\", 1, 3)"), "\"éll\"");
+        assert_eq!(eval("substr(\"abc\", 3, 0)"), "\"\"");
+        assert_eq!(eval("indexOf(\"naïve café\", \"café\")"), "6");
+        assert_eq!(eval("indexOf(\"abc\", \"z\")"), "-1");
+        assert_eq!(eval("split(\"a,b,,c\", \",\")"), "[\"a\", \"b\", \"\", \"c\"]");
+        assert_eq!(eval("split(\"hé\", \"\")"), "[\"h\", \"é\"]");
+        assert_eq!(eval("join(split(\"a b c\", \" \"), \"-\")"), "\"a-b-c\"");
+        assert_eq!(eval("upper(\"straße\") + lower(\"ÀB\")"), "\"STRASSEàb\"");
+        assert_eq!(eval("trim(\"  x \")"), "\"x\"");
+        assert_eq!(eval("replace(\"a-b-c\", \"-\", \"+\")"), "\"a+b+c\"");
+        assert_eq!(eval("startsWith(\"lox\", \"lo\") and endsWith(\"lox\", \"ox\")"), "true");
+        assert_eq!(eval("charAt(\"日本語\", 2)"), "\"語\"");
+        assert_eq!(eval("ord(\"é\")"), "233");
+        assert_eq!(eval("chr(26085)"), "\"日\"");
+    }
+
+    #[test]
+    fn test_math_natives() {
+        assert_eq!(eval("sqrt(16) + pow(2, 10)"), "1028");
+        assert_eq!(eval("floor(-1.5) + ceil(1.2) + round(2.5) + abs(-4)"), "7");
+        assert_eq!(eval("min(3, -1) * max(3, -1)"), "-3");
+        assert_eq!(eval("round(sin(PI / 2) + cos(0) + tan(0))"), "2");
+        assert_eq!(eval("atan2(1, 1) * 4 == PI"), "true");
+        assert_eq!(eval("log(exp(2))"), "2");
+        assert_eq!(eval("E"), "2.718281828459045");
+        assert_eq!(eval("isNaN(sqrt(-1)) and isInfinite(-1 / 0) and !isNaN(1)"), "true");
+        assert_eq!(eval("parseNumber(\" 12.5e1 \")"), "125");
+        assert_eq!(eval("parseNumber(\"inf\")"), "nil");
+        assert_eq!(eval("parseNumber(\"12abc\")"), "nil");
+        assert_eq!(eval("toFixed(PI, 2)"), "\"3.14\"");
+        assert_eq!(eval("toFixed(2, 0) + toFixed(0.5, 3)"), "\"20.500\"");
+        assert_eq!(eval("toFixed(1, 0.5)"), "error: toFixed: 0.5 isn't a valid index");
+    }
+
+    #[test]
+    fn test_io_natives() {
+        let dir = std::env::temp_dir().join(format!("lox_io_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("out.txt").to_string_lossy().into_owned();
+        let dir_name = dir.to_string_lossy().into_owned();
+
+        let mut interp = Interp::new();
+        assert_eq!(eval_in(&mut interp, &format!("writeFile(\"{file}\", \"one\")")), "nil");
+        assert_eq!(eval_in(&mut interp, &format!("appendFile(\"{file}\", \" two\")")), "nil");
+        assert_eq!(eval_in(&mut interp, &format!("readFile(\"{file}\")")), "\"one two\"");
+        assert_eq!(eval_in(&mut interp, &format!("fileExists(\"{file}\")")), "true");
+        assert_eq!(eval_in(&mut interp, &format!("listDir(\"{dir_name}\")")), "[\"out.txt\"]");
+        assert!(eval_in(&mut interp, &format!("readFile(\"{dir_name}/missing\")")).starts_with("error: readFile: "));
+        assert_eq!(eval_in(&mut interp, "getEnv(\"LOX_SURELY_NOT_SET\")"), "nil");
+        // what's read is charged for like anything else the script makes
+        std::fs::write(&file, "x".repeat(1 << 20)).unwrap();
+        let mut limited = Interp::new();
+        limited.set_memory_limit(Some(1 << 19));
+        assert_eq!(eval_in(&mut limited, &format!("readFile(\"{file}\")")), "error: Out of memory");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        interp.set_input(Box::new(Cursor::new("first\r\nsecond")));
+        interp.set_args(vec!["-v".to_string(), "x.txt".to_string()]);
+        assert_eq!(eval_in(&mut interp, "readLine() + readLine()"), "\"firstsecond\"");
+        assert_eq!(eval_in(&mut interp, "readLine()"), "nil");
+        assert_eq!(eval_in(&mut interp, "args()"), "[\"-v\", \"x.txt\"]");
+    }
+
+    #[test]
+    fn test_io_permissions() {
+        let mut interp = Interp::new();
+        interp.set_permissions(Permissions::none());
+        assert_eq!(eval_in(&mut interp, "readFile(\"/etc/hostname\")"), "error: readFile: reading files isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "fileExists(\"/\")"), "error: fileExists: reading files isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "writeFile(\"x\", \"y\")"), "error: writeFile: writing files isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "getEnv(\"HOME\")"), "error: getEnv: reading the environment isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "exit(1)"), "error: exit: exiting isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "readLine()"), "error: readLine: reading input isn't allowed here");
+        assert_eq!(eval_in(&mut interp, "args()"), "error: args: reading arguments isn't allowed here");
+        // a host can still hand a sandboxed script its input
+        interp.set_permissions(Permissions { input: true, ..Permissions::none() });
+        interp.set_input(Box::new(Cursor::new("hi\n")));
+        assert_eq!(eval_in(&mut interp, "readLine()"), "\"hi\"");
+    }
+
+    #[test]
+    fn test_time_natives() {
+        let mut interp = Interp::new();
+        interp.set_time_source(Box::new(FakeClock::new(1_700_000_000_250.5)));
+        assert_eq!(eval_in(&mut interp, "now()"), "1700000000250");
+        assert_eq!(eval_in(&mut interp, "clock()"), "1700000000.2505");
+        assert_eq!(eval_in(&mut interp, "sleep(1500)"), "nil");
+        assert_eq!(eval_in(&mut interp, "now()"), "1700000001750");
+        assert_eq!(eval_in(&mut interp, "sleep(-1)"), "error: sleep: can't sleep for -1 ms");
+
+        // the real clock moves forwards
+        let mut interp = Interp::new();
+        assert_eq!(eval_in(&mut interp, "now() > 1600000000000"), "true");
+    }
+
+    #[test]
+    fn test_random_natives() {
+        let draws = ["random()", "randomInt(1, 6)", "randomInt(-3, -3)", "random()"];
+        let mut interp = Interp::new();
+        assert_eq!(eval_in(&mut interp, "seed(42)"), "nil");
+        let first: Vec<String> = draws.iter().map(|draw| eval_in(&mut interp, draw)).collect();
+        interp.set_seed(42f64.to_bits());
+        let second: Vec<String> = draws.iter().map(|draw| eval_in(&mut interp, draw)).collect();
+        assert_eq!(first, second);
+        assert_eq!(first[2], "-3");
+
+        for _ in 0..100 {
+            let n: f64 = eval_in(&mut interp, "randomInt(1, 6)").parse().unwrap();
+            assert!((1.0..=6.0).contains(&n) && n.fract() == 0.0);
+            let x: f64 = eval_in(&mut interp, "random()").parse().unwrap();
+            assert!((0.0..1.0).contains(&x));
+        }
+
+        // a shuffle moves things around but keeps them all
+        let mut program = Interp::new();
+        let tokens = Scanner::new("var xs = split(\"abcdefgh\", \"\"); seed(7); shuffle(xs);".to_string()).scan_tokens();
+        program.interpret_stmts(&Parser::new(tokens).parse().unwrap()).unwrap();
+        let shuffled = eval_in(&mut program, "join(xs, \"\")");
+        assert_ne!(shuffled, "\"abcdefgh\"");
+        let mut letters: Vec<char> = shuffled.trim_matches('"').chars().collect();
+        letters.sort();
+        assert_eq!(letters.into_iter().collect::<String>(), "abcdefgh");
+
+        assert_eq!(eval_in(&mut interp, "randomInt(3, 1)"), "error: randomInt: empty range, 3 is above 1");
+        assert_eq!(eval_in(&mut interp, "randomInt(0.5, 1)"), "error: randomInt: the bounds should be whole numbers");
+    }
+
+    #[test]
+    fn test_list_natives() {
+        let mut interp = Interp::new();
+        let tokens = Scanner::new("var xs = [3, 1, 2]; var ys = xs; fun double(x) { return x * 2; } \
+                                   fun odd(x) { return x - floor(x / 2) * 2 == 1; } fun add(a, b) { return a + b; }"
+            .to_string()).scan_tokens();
+        interp.interpret_stmts(&Parser::new(tokens).parse().unwrap()).unwrap();
+
+        // ys is the same list, not a copy
+        assert_eq!(eval_in(&mut interp, "push(ys, 4)"), "nil");
+        assert_eq!(eval_in(&mut interp, "xs"), "[3, 1, 2, 4]");
+        assert_eq!(eval_in(&mut interp, "xs[1] = \"one\""), "\"one\"");
+        assert_eq!(eval_in(&mut interp, "ys[len(ys) - 3]"), "\"one\"");
+        assert_eq!(eval_in(&mut interp, "pop(xs) + remove(xs, 0)"), "7");
+        assert_eq!(eval_in(&mut interp, "insert(xs, 2, 9)"), "nil");
+        assert_eq!(eval_in(&mut interp, "insert(xs, 0, 0)"), "nil");
+        assert_eq!(eval_in(&mut interp, "ys"), "[0, \"one\", 2, 9]");
+        assert_eq!(eval_in(&mut interp, "slice(xs, 1, 3)"), "[\"one\", 2]");
+        assert_eq!(eval_in(&mut interp, "slice(xs, 4, 4)"), "[]");
+
+        assert_eq!(eval_in(&mut interp, "map([1, 2, 3], double)"), "[2, 4, 6]");
+        assert_eq!(eval_in(&mut interp, "filter([1, 2, 3, 5], odd)"), "[1, 3, 5]");
+        assert_eq!(eval_in(&mut interp, "reduce([1, 2, 3], add, 10)"), "16");
+        assert_eq!(eval_in(&mut interp, "[[1], [2, [3]]][1][1][0]"), "3");
+        assert_eq!(eval_in(&mut interp, "range(-1, 2)"), "range(-1, 2)");
+        assert_eq!(eval_in(&mut interp, "map(range(-1, 2), double)"), "[-2, 0, 2]");
+        assert_eq!(eval_in(&mut interp, "join(range(0.5, 2), \" \")"), "\"0.5 1.5\"");
+        assert_eq!(eval_in(&mut interp, "[len(range(0.5, 2)), len(range(3, 3)), len(range(3, 1))]"), "[2, 0, 0]");
+        assert_eq!(eval_in(&mut interp, "range(0.5, 2)[1]"), "1.5");
+        assert_eq!(eval_in(&mut interp, "range(0.5, 2)[2]"), "error: index 2 is out of range for a list of length 2");
+        assert_eq!(eval_in(&mut interp, "range(0, 3) == range(0, 3)"), "true");
+        assert_eq!(eval_in(&mut interp, "range(0, 3)[0] = 1"), "error: can't assign into a range");
+        assert_eq!(eval_in(&mut interp, "push(range(0, 3), 3)"), "error: push: argument 1 should be a list, not a range");
+        assert_eq!(eval_in(&mut interp, "range(0, 1 / 0)"), "error: range: the bounds should be between -2^53 and 2^53");
+        // fine until it has to be made into a list
+        assert_eq!(eval_in(&mut interp, "len(range(0, 1000000000000))"), "1000000000000");
+        assert_eq!(eval_in(&mut interp, "filter(range(0, 1000000000000), odd)"), "error: filter: that's 1000000000000 numbers, a list of them can hold at most 16777216");
+        assert_eq!(eval_in(&mut interp, "[1] == [1]"), "false");
+        assert_eq!(eval_in(&mut interp, "xs == ys"), "true");
+        // a list that holds itself still prints
+        assert_eq!(eval_in(&mut interp, "push(xs, xs)"), "nil");
+        assert_eq!(eval_in(&mut interp, "slice(xs, 3, 5)"), "[9, [0, \"one\", 2, 9, [...]]]");
+    }
+
+    #[test]
+    fn test_map_natives() {
+        let mut interp = Interp::new();
+        let tokens = Scanner::new("var m = {\"b\": 1, \"a\": 2, 3: true, nil: \"none\"}; var alias = m;".to_string())
+            .scan_tokens();
+        interp.interpret_stmts(&Parser::new(tokens).parse().unwrap()).unwrap();
+
+        assert_eq!(eval_in(&mut interp, "m[\"a\"] + m[\"b\"]"), "3");
+        assert_eq!(eval_in(&mut interp, "m[3] and m[nil] == \"none\""), "true");
+        // -0 and 0 are the same key
+        assert_eq!(eval_in(&mut interp, "m[-0] = \"zero\""), "\"zero\"");
+        assert_eq!(eval_in(&mut interp, "m[0]"), "\"zero\"");
+        // a new key goes on the end, an old one stays where it was
+        assert_eq!(eval_in(&mut interp, "alias[\"b\"] = 10"), "10");
+        assert_eq!(eval_in(&mut interp, "keys(m)"), "[\"b\", \"a\", 3, nil, 0]");
+        assert_eq!(eval_in(&mut interp, "values(m)"), "[10, 2, true, \"none\", \"zero\"]");
+        assert_eq!(eval_in(&mut interp, "has(m, \"a\") and !has(m, \"c\")"), "true");
+        assert_eq!(eval_in(&mut interp, "delete(m, \"a\")"), "2");
+        assert_eq!(eval_in(&mut interp, "delete(m, \"a\")"), "nil");
+        assert_eq!(eval_in(&mut interp, "len(m)"), "4");
+        assert_eq!(eval_in(&mut interp, "m[0] = m"), "{\"b\": 10, 3: true, nil: \"none\", 0: {...}}");
+        assert_eq!(eval_in(&mut interp, "{\"x\": 1} == {\"x\": 1}"), "false");
+
+        assert_eq!(eval_in(&mut interp, "m[\"a\"]"), "error: key \"a\" isn't in the map");
+        assert_eq!(eval_in(&mut interp, "m[[]] = 1"), "error: a list can't be a map key");
+        assert_eq!(eval_in(&mut interp, "{sqrt(-1): 1}"), "error: nan can't be a map key");
+        assert_eq!(eval_in(&mut interp, "has(m, m)"), "error: has: a map can't be a map key");
+        assert_eq!(eval_in(&mut interp, "keys([])"), "error: keys: argument 1 should be a map, not a list");
+    }
+
+    #[test]
+    fn test_list_errors() {
+        assert_eq!(eval("[1, 2][2]"), "error: index 2 is out of range for a list of length 2");
+        assert_eq!(eval("[1, 2][-1] = 0"), "error: index -1 is out of range for a list of length 2");
+        assert_eq!(eval("[1][0.5]"), "error: index 0.5 is out of range for a list of length 1");
+        assert_eq!(eval("[1][\"0\"]"), "error: list index must be a number, not a string");
+        assert_eq!(eval("\"abc\"[0]"), "error: can only index lists, maps and ranges, not a string");
+        assert_eq!(eval("pop([])"), "error: pop: can't pop from an empty list");
+        assert_eq!(eval("remove([1], 1)"), "error: remove: index 1 is out of range for a list of length 1");
+        assert_eq!(eval("insert([1], 2, 0)"), "error: insert: index 2 is out of range for a list of length 1");
+        assert_eq!(eval("slice([1, 2], 2, 1)"), "error: slice: start 2 is after end 1");
+        assert_eq!(eval("map([1], 2)"), "error: map: argument 2 should be a function, not a number");
+        assert_eq!(eval("map([1], pow)"), "error: map: the function should take 1 argument, pow takes 2");
+        assert_eq!(eval("reduce([1], sqrt, 0)"), "error: reduce: the function should take 2 arguments, sqrt takes 1");
+
+        // the error points at the ']'
+        let tokens = Scanner::new("[1,\n2]\n[\n5\n]".to_string()).scan_tokens();
+        let expr = Parser::new(tokens).parse_lone_expr().unwrap();
+        let err = Interp::new().evaluate(&expr).unwrap_err();
+        assert_eq!((err.token.lexeme.as_str(), err.token.line), ("]", 5));
+    }
+
+    #[test]
+    fn test_string_native_errors() {
+        assert_eq!(eval("charAt(\"abc\", 3)"), "error: charAt: index 3 is out of range for a string of length 3");
+        assert_eq!(eval("substr(\"abc\", 1, 5)"), "error: substr: index 6 is out of range for a string of length 3");
+        assert_eq!(eval("substr(\"abc\", -1, 1)"), "error: substr: -1 isn't a valid index");
+        assert_eq!(eval("charAt(\"abc\", 1.5)"), "error: charAt: 1.5 isn't a valid index");
+        assert_eq!(eval("len(3)"), "error: len: argument 1 should be a string, list, map or range, not a number");
+        assert_eq!(eval("ord(\"ab\")"), "error: ord: expected a string of exactly one character");
+        assert_eq!(eval("chr(55296)"), "error: chr: 55296 isn't a Unicode scalar value");
+        assert_eq!(eval("upper(1, 2)"), "error: Arity mismatch: func wants: 1, # args given: 2");
+    }
+}
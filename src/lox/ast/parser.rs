@@ -1,44 +1,161 @@
 use crate::lox::ast;
 use crate::lox::ast::{Expr, Binary, Unary, Grouping, Stmt, Assign};
-use crate::token_type::{self, Literal, Token, TokenType};
-use crate::lox::error;
+use crate::token_type::{self, Literal, Token, TokenType, RESERVED_KEYWORDS};
 
-use std::error::Error;
 
-use super::{Call, FuncStmt, IfStmt, Logical, WhileStmt};
-use super::{Variable, interpreter::RuntimeError};
+use super::{Call, CatchClause, Conditional, ForInStmt, ForStmt, FuncStmt, IfStmt, Index, IndexAssign, Lambda, ListExpr, Logical, LoopJump, MapExpr, ReturnStmt, ThrowStmt, TryStmt, WhileStmt};
+use super::Variable;
+use super::stack;
 
 
 
 
 pub struct Parser {
     tokens: Vec<Token>,
-    current: i32
+    current: i32,
+    // (first line, last line) of every statement, in the order the statements
+    // start - i.e. a pre-order walk of the tree. Stmt doesn't carry line
+    // numbers itself; the formatter uses these to put comments back.
+    stmt_spans: Vec<(usize, usize)>,
+    // how many nested() parses we're inside, see MAX_NESTING
+    nesting: usize,
+}
+
+// Every level of brackets, nested block, `- - x` etc. costs a stack of
+// recursive calls here (and in every visitor afterwards), so a silly deep
+// program is an error rather than a native stack overflow. A chain like
+// `a + b + c` is as deep as it is long, ((a + b) + c), so each operator in
+// one counts as a level too. On a small stack the parser stops sooner, see
+// stack.rs.
+pub const MAX_NESTING: usize = 200;
+
+// Separate from RuntimeError: these stop a program from running at all.
+// Reported with lox::error.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub token: Token,
+    pub message: String,
+    // a suggested fix, e.g. "did you mean 'fun'?"
+    pub hint: Option<String>,
+}
+
+impl ParseError {
+    fn new(token: Token, message: String) -> Self {
+        Self { token, message, hint: None }
+    }
+}
+
+// how a token is shown in "..., found X" messages
+fn describe(token: &Token) -> String {
+    if token.token_type == TokenType::EOF {
+        "end of file".to_string()
+    } else {
+        format!("'{}'", token.lexeme)
+    }
+}
+
+// Edit distance where swapping two neighbouring letters counts as one edit,
+// since "retrun" is a much likelier typo than two unrelated changes.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() { row[0] = i; }
+    for (j, cell) in d[0].iter_mut().enumerate() { *cell = j; }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// the keyword an identifier was probably meant to be, if any
+fn similar_keyword(word: &str) -> Option<&'static str> {
+    let max_distance = if word.chars().count() <= 4 { 1 } else { 2 };
+    let mut keywords: Vec<&'static str> = RESERVED_KEYWORDS.keys().copied().collect();
+    keywords.sort();  // HashMap order isn't stable; ties go to the first alphabetically
+    keywords.into_iter()
+        .map(|keyword| (edit_distance(word, keyword), keyword))
+        .filter(|(distance, _)| *distance > 0 && *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self{
         Parser{
             tokens,
-            current: 0
+            current: 0,
+            stmt_spans: Vec::new(),
+            nesting: 0,
         }
     }
-    pub fn parse(&mut self) -> Vec<Stmt> {
+
+    // Parses the whole program. On a syntax error we synchronise and carry on,
+    // so one run reports every error, but then only the errors come back.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut out = Vec::new();
+        let mut errors = Vec::new();
         while !self.is_at_end() {
-            // out.push(self.declaration().unwrap());
             match self.declaration() {
                 Ok(stmt) => {
-                    println!("stmt in parse: {stmt:?}");
                     out.push(stmt);
                 }
                 Err(e) => {
-                    error(&e.token, &e.message);
+                    errors.push(e);
                     self.synchronise();
                 }
             }
         }
-        out
+        if errors.is_empty() {
+            Ok(out)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn stmt_spans(&self) -> Vec<(usize, usize)> {
+        self.stmt_spans.clone()
+    }
+
+    // call with the line of the statement's first token, before parsing any
+    // of its children
+    fn begin_stmt(&mut self, line: usize) -> usize {
+        self.stmt_spans.push((line, line));
+        self.stmt_spans.len() - 1
+    }
+
+    fn end_stmt(&mut self, span: usize) {
+        self.stmt_spans[span].1 = self.previous().line;
+    }
+
+    // parse(self), one level deeper. Wrapped around the parses that recurse:
+    // statements and expressions inside others, and chains like `a = b = c`.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if stack::running_low() {
+            return Err(ParseError::new(self.peek().clone(), "nested too deeply for the stack there is".to_string()));
+        }
+        let nesting = self.nesting;
+        self.deeper()?;
+        let result = parse(self);
+        // a chain that stopped at an error hasn't put its levels back
+        self.nesting = nesting;
+        result
+    }
+
+    // One level deeper without recursing, for each operator in a chain. The
+    // chain sets `nesting` back once it's done.
+    fn deeper(&mut self) -> Result<(), ParseError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(ParseError::new(self.peek().clone(), format!("nested too deeply, the limit is {MAX_NESTING} levels")));
+        }
+        self.nesting += 1;
+        Ok(())
     }
 
     fn synchronise(&mut self) {
@@ -50,7 +167,9 @@ impl Parser {
             match self.peek().token_type {
                 TokenType::CLASS |TokenType::FUN |TokenType::VAR |
                 TokenType::FOR |TokenType::IF |TokenType::WHILE |
-                TokenType::PRINT |TokenType::RETURN => {return;}
+                TokenType::PRINT |TokenType::RETURN |
+                TokenType::BREAK |TokenType::CONTINUE |
+                TokenType::THROW |TokenType::TRY => {return;}
                 _ => {self.advance();}
             }
 
@@ -58,14 +177,50 @@ impl Parser {
     }
 
     //TODO add in ParseError's. And synchronize??
-    fn declaration(&mut self) -> Result<Stmt, RuntimeError> {
+    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.current as usize;
+        self.declaration_inner().map_err(|mut err| {
+            // `fucn foo() {}` / `retrun x;` fail somewhere after the typo, so
+            // look back at how the statement started
+            if err.hint.is_none() && self.looks_like_keyword_typo(start, &err.token) {
+                err.hint = self.keyword_hint(start);
+            }
+            if err.hint.is_none() {
+                err.hint = self.keyword_hint(self.current as usize);
+            }
+            err
+        })
+    }
+
+    // `foo(1 2)` shouldn't suggest `for`: only blame the first word when
+    // what follows it makes no sense for a variable - another word/value
+    // (`retrun x;`), or a `{` after something call-like (`whiel (x) {`).
+    fn looks_like_keyword_typo(&self, start: usize, error_token: &Token) -> bool {
+        let Some(next) = self.tokens.get(start + 1) else { return false };
+        matches!(next.token_type,
+            TokenType::IDENTIFIER | TokenType::STRING | TokenType::NUMBER |
+            TokenType::TRUE | TokenType::FALSE | TokenType::NIL)
+            || error_token.token_type == TokenType::LEFT_BRACE
+    }
+
+    fn keyword_hint(&self, index: usize) -> Option<String> {
+        let token = self.tokens.get(index)?;
+        if token.token_type != TokenType::IDENTIFIER {
+            return None;
+        }
+        similar_keyword(&token.lexeme).map(|keyword| format!("did you mean '{keyword}'?"))
+    }
+
+    fn declaration_inner(&mut self) -> Result<Stmt, ParseError> {
         // we only allow var declarations at this level,
         // i.e. top level - so within control statements not longer allowed
         // to var_decl I guess?
         if self.match_types(&[TokenType::VAR]) {
             self.var_declaration()
 
-        } else if self.match_types(&[TokenType::FUN]) {
+        } else if self.check(TokenType::FUN) && !self.check_ahead(1, TokenType::LEFT_PAREN) {
+            // `fun (x) {...}` without a name is a lambda, in an expression statement
+            self.advance();
             self.func_declaration()
         } else {
             self.statement()
@@ -76,58 +231,111 @@ impl Parser {
         //synchronize? 
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, RuntimeError> {
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
         // either "name;" or "name = expr;"
-        let name = self.consume(TokenType::IDENTIFIER, "expected IDENTIFIER in var declaration")?;
+        let name = self.consume(TokenType::IDENTIFIER, "expected variable name after 'var'")?;
         if self.match_types(&[TokenType::EQUAL]) {
             let initializer = self.expression()?;
-            self.consume(TokenType::SEMICOLON, "Expected ';' after value")?;
+            self.consume(TokenType::SEMICOLON, "expected ';' after variable declaration")?;
+            self.end_stmt(span);
             return Ok(Stmt::Var(Variable{name: name, initializer: initializer}))
         } else {
             // We set uninitialised variables to Nil. This seeems reasonable, although
             // we could instead raise a runtime error if accessing a non-initialised
             // variable.
             let initializer = Expr::Null;
-            self.consume(TokenType::SEMICOLON, "Expected ';' after value")?;
+            self.consume(TokenType::SEMICOLON, "expected ';' after variable declaration")?;
+            self.end_stmt(span);
             return Ok(Stmt::Var(Variable{name: name, initializer: initializer}))
         }
     }
 
-    fn func_declaration(&mut self) -> Result<Stmt, RuntimeError> {
-        let name = self.consume(TokenType::IDENTIFIER, "fun declaration lacking identifier")?;
-        self.consume(TokenType::LEFT_PAREN, "expect '(' after func identifier decl")?;
-
+    fn func_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let name = self.consume(TokenType::IDENTIFIER, "expected function name after 'fun'")?;
+        self.consume(TokenType::LEFT_PAREN, "expected '(' after function name")?;
+        let parameters = self.parameters()?;
+        self.consume(TokenType::LEFT_BRACE, "expected '{' before function body")?;
+        let body = self.block()?;
+        self.end_stmt(span);
+        Ok(Stmt::Func(FuncStmt{
+            name,
+            parameters,
+            body
+        }))
+    }
 
-        // immutable borrow here if no .clone()?
-        let current = self.peek().clone();
+    // the names up to and including the ')', once the '(' has been consumed
+    fn parameters(&mut self) -> Result<Vec<Token>, ParseError> {
         let mut parameters: Vec<Token> = Vec::new();
-        while !self.check(TokenType::RIGHT_PAREN) {
-            // error if >= 255 args
-            if parameters.len() >= 255 {
-                return Err(RuntimeError{token: current.clone(), message: "too many arguments".to_string()})?;
-            }
-            parameters.push(self.consume(TokenType::IDENTIFIER, "expected IDENTIFIER arg, got something else")?);
-            if self.check(TokenType::COMMA) {
-                self.advance();
+        if !self.check(TokenType::RIGHT_PAREN) {
+            loop {
+                // error if >= 255 args
+                if parameters.len() >= 255 {
+                    return Err(ParseError::new(self.peek().clone(), "can't have more than 255 parameters".to_string()));
+                }
+                parameters.push(self.consume(TokenType::IDENTIFIER, "expected parameter name")?);
+                if !self.match_types(&[TokenType::COMMA]) {
+                    break;
+                }
             }
-            // println!("args in finish_call: {args:?}");
         }
-        self.consume(TokenType::RIGHT_PAREN, "Expected ')' after arguments")?;
-        self.consume(TokenType::LEFT_BRACE, "Expected opening brace for func body")?;
-        let body = self.block_statement()?;
-        if let Stmt::Block(body) = body {
-            return Ok(Stmt::Func(FuncStmt{
-                name,
-                parameters,
-                body
-            }));
+        self.consume(TokenType::RIGHT_PAREN, "expected ',' or ')' after parameter")?;
+        Ok(parameters)
+    }
+
+    // fun (a, b) { ... }, with the `fun` consumed. The body gets a span like a
+    // declaration's so the formatter knows where its braces were.
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let keyword = self.previous();
+        let span = self.begin_stmt(keyword.line);
+        self.consume(TokenType::LEFT_PAREN, "expected '(' after 'fun'")?;
+        let parameters = self.parameters()?;
+        self.consume(TokenType::LEFT_BRACE, "expected '{' before function body")?;
+        let body = self.block()?;
+        self.end_stmt(span);
+        Ok(Expr::Lambda(Lambda { keyword, parameters, body, arrow: false }))
+    }
+
+    // (a, b) => a + b, with the '(' consumed
+    fn arrow_function(&mut self) -> Result<Expr, ParseError> {
+        let parameters = self.parameters()?;
+        let keyword = self.consume(TokenType::ARROW, "expected '=>' after parameters")?;
+        let value = self.expression()?;
+        let body = vec![Stmt::Return(ReturnStmt { keyword: keyword.clone(), value })];
+        Ok(Expr::Lambda(Lambda { keyword, parameters, body, arrow: true }))
+    }
+
+    // Is the '(' we're on the start of `(a, b) =>` rather than a grouping?
+    fn starts_arrow_function(&self) -> bool {
+        let mut distance = 1;
+        if self.check_ahead(distance, TokenType::RIGHT_PAREN) {
+            return self.check_ahead(distance + 1, TokenType::ARROW);
+        }
+        while self.check_ahead(distance, TokenType::IDENTIFIER) {
+            if self.check_ahead(distance + 1, TokenType::RIGHT_PAREN) {
+                return self.check_ahead(distance + 2, TokenType::ARROW);
+            }
+            if !self.check_ahead(distance + 1, TokenType::COMMA) {
+                return false;
+            }
+            distance += 2;
         }
-        Err(RuntimeError{token: name, message: "Expected block statement for function body".to_string()})
+        false
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        self.nested(Self::statement_inner)
     }
 
-    fn statement(&mut self) -> Result<Stmt, RuntimeError> {
+    fn statement_inner(&mut self) -> Result<Stmt, ParseError> {
         if self.match_types(&[TokenType::PRINT]) {
             self.print_statement()
+        } else if self.check(TokenType::LEFT_BRACE) && self.starts_map_literal() {
+            // `{"a": 1}[k];`, an expression statement that happens to start
+            // with a map
+            self.expr_statement()
         } else if self.match_types(&[TokenType::LEFT_BRACE]) {
             // This is a Stmt::Block(Vec<Stmt>), unlike the other
             // foolish single Stmt types.
@@ -135,9 +343,19 @@ impl Parser {
         } else if self.match_types(&[TokenType::IF]) {
             self.if_statement()
         } else if self.match_types(&[TokenType::WHILE]) {
-            self.while_statement()
+            self.while_statement(None)
         } else if self.match_types(&[TokenType::FOR]) {
-            self.for_statement()
+            self.for_statement(None)
+        } else if self.check(TokenType::IDENTIFIER) && self.check_ahead(1, TokenType::COLON) {
+            self.labelled_statement()
+        } else if self.match_types(&[TokenType::RETURN]) {
+            self.return_statement()
+        } else if self.match_types(&[TokenType::BREAK, TokenType::CONTINUE]) {
+            self.jump_statement()
+        } else if self.match_types(&[TokenType::THROW]) {
+            self.throw_statement()
+        } else if self.match_types(&[TokenType::TRY]) {
+            self.try_statement()
         } else {
             self.expr_statement()
         }
@@ -145,27 +363,63 @@ impl Parser {
         // Stmt::Print(Expr::Literal(Literal::Nil))
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, RuntimeError> {
+    // At the start of a statement a '{' is a block, unless a ':' turns up
+    // before anything that ends the first statement of one (a ';' or the
+    // closing '}'). `{}` on its own is an empty block, `{ outer: while`
+    // is a block starting with a labelled loop, and the ':' of a `?:`
+    // doesn't count.
+    fn starts_map_literal(&self) -> bool {
+        let mut depth = 0;
+        let mut open_questions = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(self.current as usize + 1) {
+            match token.token_type {
+                TokenType::LEFT_PAREN | TokenType::LEFT_BRACKET | TokenType::LEFT_BRACE => depth += 1,
+                TokenType::RIGHT_PAREN | TokenType::RIGHT_BRACKET | TokenType::RIGHT_BRACE if depth > 0 => depth -= 1,
+                TokenType::QUESTION if depth == 0 => open_questions += 1,
+                TokenType::COLON if depth == 0 && open_questions > 0 => open_questions -= 1,
+                TokenType::COLON if depth == 0 => {
+                    let next = self.tokens.get(i + 1).map(|token| token.token_type);
+                    return !matches!(next, Some(TokenType::WHILE | TokenType::FOR));
+                }
+                TokenType::SEMICOLON | TokenType::RIGHT_BRACE | TokenType::EOF if depth == 0 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
         let expr = self.expression()?;
-        self.consume(TokenType::SEMICOLON, "Expected ';' after value")?;
+        self.consume(TokenType::SEMICOLON, "expected ';' after value")?;
+        self.end_stmt(span);
         Ok(Stmt::Print(expr))
     }
 
-    fn block_statement(&mut self) -> Result<Stmt, RuntimeError> {
+    fn block_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let statements = self.block()?;
+        self.end_stmt(span);
+        Ok(Stmt::Block(statements))
+    }
+
+    // the statements up to and including the closing '}'. Shared by blocks and
+    // function bodies.
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements: Vec<Stmt> = Vec::new();
 
         while !self.check(TokenType::RIGHT_BRACE) && !self.is_at_end() {
-            let current = self.peek();
             statements.push(self.declaration()?)
         }
-        self.consume(TokenType::RIGHT_BRACE, "Expected '}' after block")?;
-        Ok(Stmt::Block(statements))
+        self.consume(TokenType::RIGHT_BRACE, "expected '}' after block")?;
+        Ok(statements)
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, RuntimeError> {
-        self.consume(TokenType::LEFT_PAREN, "Expect '(' after IF");
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        self.consume(TokenType::LEFT_PAREN, "expected '(' after 'if'")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RIGHT_PAREN, "Expected ')' closing IF condition");
+        self.consume(TokenType::RIGHT_PAREN, "expected ')' after if condition")?;
         // NB not a declaration (if we want var_decl then must be in Block Stmt)
         let if_branch = Box::new(self.statement()?);
         let else_branch = if self.match_types(&[TokenType::ELSE]) {
@@ -173,20 +427,60 @@ impl Parser {
         } else {
             None
         };
+        self.end_stmt(span);
         return Ok(Stmt::If(IfStmt{condition, if_branch, else_branch}))
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, RuntimeError> {
-        self.consume(TokenType::LEFT_PAREN, "Expect '(' after WHILE");
+    // outer: while (...) ... - only loops can be labelled
+    fn labelled_statement(&mut self) -> Result<Stmt, ParseError> {
+        let label = self.advance();
+        self.advance();  // the ':'
+        if self.match_types(&[TokenType::WHILE]) {
+            self.while_statement(Some(label))
+        } else if self.match_types(&[TokenType::FOR]) {
+            self.for_statement(Some(label))
+        } else {
+            let token = self.peek().clone();
+            Err(ParseError::new(token.clone(), format!("expected a loop after label '{}', found {}", label.lexeme, describe(&token))))
+        }
+    }
+
+    fn jump_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let keyword = self.previous();
+        let label = if self.check(TokenType::IDENTIFIER) { Some(self.advance()) } else { None };
+        self.consume(TokenType::SEMICOLON, &format!("expected ';' after '{}'", keyword.lexeme))?;
+        self.end_stmt(span);
+        let is_break = keyword.token_type == TokenType::BREAK;
+        let jump = LoopJump { keyword, label };
+        Ok(if is_break { Stmt::Break(jump) } else { Stmt::Continue(jump) })
+    }
+
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let keyword = self.previous();
+        self.consume(TokenType::LEFT_PAREN, "expected '(' after 'while'")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RIGHT_PAREN, "Expected ')' closing WHILE condition");
+        self.consume(TokenType::RIGHT_PAREN, "expected ')' after while condition")?;
         // NB not a declaration (if we want var_decl then must be in Block Stmt)
         let body = Box::new(self.statement()?);
-        return Ok(Stmt::While(WhileStmt{condition, body}))
+        self.end_stmt(span);
+        return Ok(Stmt::While(WhileStmt{label, keyword, condition, body}))
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, RuntimeError> {
-        self.consume(TokenType::LEFT_PAREN, "Expect '(' after FOR");
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let keyword = self.previous();
+        self.consume(TokenType::LEFT_PAREN, "expected '(' after 'for'")?;
+        if self.check_ahead(2, TokenType::IN) {
+            return self.for_in_statement(span, label);
+        }
+        if self.check(TokenType::IDENTIFIER) && self.check_ahead(1, TokenType::IN) {
+            let name = self.peek().clone();
+            let mut err = ParseError::new(name.clone(), format!("expected 'var' before the loop variable, found {}", describe(&name)));
+            err.hint = Some(format!("each time round is a new variable, write `for (var {} in ...)`", name.lexeme));
+            return Err(err);
+        }
         let initializer: Option<Stmt> = if self.match_types(&[TokenType::SEMICOLON]) {
             None
         } else if self.match_types(&[TokenType::VAR]) {
@@ -200,65 +494,184 @@ impl Parser {
         } else {
             None
         };
-        self.consume(TokenType::SEMICOLON, "Expected ';' before increment in FOR");
+        self.consume(TokenType::SEMICOLON, "expected ';' after loop condition")?;
 
         let increment = if !self.check(TokenType::RIGHT_PAREN) {
             Some(self.expression()?)
         } else {
             None
         };
-        self.consume(TokenType::RIGHT_PAREN, "Expected final ')' in FOR");
+        self.consume(TokenType::RIGHT_PAREN, "expected ')' after for clauses")?;
         
-        let mut body = self.statement()?;
+        let body = self.statement()?;
+        self.end_stmt(span);
+
+        // No desugaring into a WhileStmt here any more - see ForStmt.
+        return Ok(Stmt::For(ForStmt{
+            label,
+            keyword,
+            initializer: initializer.map(Box::new),
+            condition,
+            increment,
+            body: Box::new(body),
+        }));
+    }
 
-        if let Some(inc) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(inc)]);
-        }
-        let condition = condition.unwrap_or(Expr::Literal(Literal::Boolean(true)));
-        body = Stmt::While(WhileStmt{condition: condition, body: Box::new(body)});
-        
-        if let Some(init) = initializer{
-            body = Stmt::Block(vec![init, body]);
+    // for (var x in xs) body, once for_statement has seen the `in` coming
+    fn for_in_statement(&mut self, span: usize, label: Option<Token>) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::VAR, "expected 'var' before the loop variable")?;
+        let name = self.consume(TokenType::IDENTIFIER, "expected loop variable name")?;
+        let keyword = self.consume(TokenType::IN, "expected 'in' after loop variable")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RIGHT_PAREN, "expected ')' after for-in clause")?;
+
+        let body = self.statement()?;
+        self.end_stmt(span);
+        Ok(Stmt::ForIn(ForInStmt { label, name, keyword, iterable, body: Box::new(body) }))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.previous().line);
+        let keyword = self.previous();
+        let value = if self.check(TokenType::SEMICOLON) {
+            Expr::Null
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::SEMICOLON, "expected ';' after return value")?;
+        self.end_stmt(span);
+        Ok(Stmt::Return(ReturnStmt { keyword, value }))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let span = self.begin_stmt(keyword.line);
+        let value = self.expression()?;
+        self.consume(TokenType::SEMICOLON, "expected ';' after thrown value")?;
+        self.end_stmt(span);
+        Ok(Stmt::Throw(ThrowStmt{keyword, value}))
+    }
+
+    // Each of the blocks gets a span of its own, for the formatter, on top
+    // of the one for the whole statement.
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let span = self.begin_stmt(keyword.line);
+        let body = self.clause_block("try")?;
+        let catch = if self.match_types(&[TokenType::CATCH]) {
+            self.consume(TokenType::LEFT_PAREN, "expected '(' after 'catch'")?;
+            let name = self.consume(TokenType::IDENTIFIER, "expected a name for the caught error")?;
+            self.consume(TokenType::RIGHT_PAREN, "expected ')' after the caught error's name")?;
+            Some(CatchClause{name, body: self.clause_block(")")?})
+        } else {
+            None
+        };
+        let finally = if self.match_types(&[TokenType::FINALLY]) {
+            Some(self.clause_block("finally")?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            let token = self.peek().clone();
+            return Err(ParseError::new(token.clone(), format!("expected 'catch' or 'finally' after try block, found {}", describe(&token))));
         }
+        self.end_stmt(span);
+        Ok(Stmt::Try(TryStmt{keyword, body, catch, finally}))
+    }
 
-        return Ok(body);
+    fn clause_block(&mut self, after: &str) -> Result<Vec<Stmt>, ParseError> {
+        let brace = self.consume(TokenType::LEFT_BRACE, &format!("expected '{{' after '{after}'"))?;
+        let span = self.begin_stmt(brace.line);
+        let statements = self.block()?;
+        self.end_stmt(span);
+        Ok(statements)
     }
 
-    fn expr_statement(&mut self) -> Result<Stmt, RuntimeError> {
+    fn expr_statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.begin_stmt(self.peek().line);
         let expr = self.expression()?;
-        self.consume(TokenType::SEMICOLON, "Expected ';' after expression")?;
+        self.consume(TokenType::SEMICOLON, "expected ';' after expression")?;
+        self.end_stmt(span);
         Ok(Stmt::Expression(expr))
     }
 
-    pub fn parse_expr(&mut self) -> Option<Expr>{
-        self.expression().ok()
+    // For the REPL: the whole input is one expression, with or without a
+    // trailing ';', e.g. `1 + 2`.
+    pub fn parse_lone_expr(&mut self) -> Option<Expr> {
+        let expr = self.expression().ok()?;
+        self.match_types(&[TokenType::SEMICOLON]);
+        if self.is_at_end() {
+            Some(expr)
+        } else {
+            None
+        }
     }
 
-    fn expression(&mut self) -> Result<Expr, RuntimeError> {
-        self.assignment()
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.nested(Self::assignment)
     }
 
-    fn assignment(&mut self) -> Result<Expr, RuntimeError> {
-        let expr = self.or()?;
-        if self.match_types(&[TokenType::EQUAL]) {
-            let equals_token = self.previous();
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.conditional()?;
+        if self.match_types(&[TokenType::EQUAL, TokenType::PLUS_EQUAL, TokenType::MINUS_EQUAL,
+                              TokenType::STAR_EQUAL, TokenType::SLASH_EQUAL, TokenType::PERCENT_EQUAL]) {
+            let operator = self.previous();
             if let Expr::Variable ( token ) = expr {
                 // Letting right be of type self.assignment, not one precedence level down
                 // I think makes this right associative?
-                let right = self.assignment()?;
-                return Ok(Expr::Assign(Assign{name: token, value: Box::new(right)}))
+                let right = self.nested(Self::assignment)?;
+                return Ok(Expr::Assign(Assign{name: token, operator, value: Box::new(right)}))
+            } else if let Expr::Index(Index{object, bracket, index, optional: None}) = expr {
+                let right = self.nested(Self::assignment)?;
+                return Ok(Expr::IndexAssign(IndexAssign{object, bracket, index, operator, value: Box::new(right)}))
             } else {
-                return Err(RuntimeError{token: equals_token , message: "trailing equal sign in non assignment expression??".to_string()})?;
+                return Err(ParseError::new(operator, "invalid assignment target, only variables, list elements and map entries can be assigned to".to_string()));
             }
         } else {
-            // return Err(RuntimeError{token: self.previous() , message: "trailing equal sign in non assignment expression??".to_string()})?;
             return Ok(expr);
         }
     }
 
-    fn or(&mut self) -> Result<Expr, RuntimeError> {
+    // the middle can be any expression, the ':' closes it off like a ')'
+    fn conditional(&mut self) -> Result<Expr, ParseError> {
+        let condition = self.coalesce()?;
+        if !self.match_types(&[TokenType::QUESTION]) {
+            return Ok(condition);
+        }
+        let question = self.previous();
+        let then_branch = self.expression()?;
+        self.consume(TokenType::COLON, "expected ':' after the then branch of '?'")?;
+        let else_branch = self.nested(Self::conditional)?;
+        Ok(Expr::Conditional(Conditional{
+            condition: Box::new(condition),
+            question,
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        }))
+    }
+
+    // a ?? b, looser than `or` so `a or b ?? c` is `(a or b) ?? c`
+    fn coalesce(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.or()?;
+        let nesting = self.nesting;
+        while self.match_types(&[TokenType::QUESTION_QUESTION]) {
+            self.deeper()?;
+            let operator = self.previous();
+            let right = self.or()?;
+            expr = Expr::Logical(Logical{
+                operator,
+                left: Box::new(expr),
+                right: Box::new(right)});
+        }
+        self.nesting = nesting;
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
+        let nesting = self.nesting;
         while self.match_types(&[TokenType::OR]) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.and()?;
             expr = Expr::Logical(Logical{
@@ -266,12 +679,15 @@ impl Parser {
                 left: Box::new(expr),
                 right: Box::new(right)});
         }
+        self.nesting = nesting;
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, RuntimeError> {
+    fn and(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.equality()?;
+        let nesting = self.nesting;
         while self.match_types(&[TokenType::AND]) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.equality()?;
             expr = Expr::Logical(Logical{
@@ -279,15 +695,18 @@ impl Parser {
                 left: Box::new(expr),
                 right: Box::new(right)});
         }
+        self.nesting = nesting;
         Ok(expr)
     }
 
 
-    fn equality(&mut self) -> Result<Expr, RuntimeError> {
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.comparison()?;
+        let nesting = self.nesting;
         
         while self.match_types(
             &[TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.comparison()?;
             expr = Expr::Binary(Binary{
@@ -297,16 +716,19 @@ impl Parser {
             })
         }
 
+        self.nesting = nesting;
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, RuntimeError> {
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.term()?;
+        let nesting = self.nesting;
 
         while self.match_types(
             &[TokenType::GREATER, TokenType::GREATER_EQUAL,
             TokenType::LESS, TokenType::LESS_EQUAL]
         ) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.term()?;
             expr = Expr::Binary(Binary{
@@ -315,15 +737,18 @@ impl Parser {
                 right: Box::new(right)
             })
         }
+        self.nesting = nesting;
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, RuntimeError> {
+    fn term(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.factor()?;
+        let nesting = self.nesting;
 
         while self.match_types(
             &[TokenType::MINUS, TokenType::PLUS]
         ) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.factor()?;
             expr = Expr::Binary(Binary{
@@ -332,15 +757,18 @@ impl Parser {
                 right: Box::new(right)
             })
         }
+        self.nesting = nesting;
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, RuntimeError> {
+    fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
+        let nesting = self.nesting;
 
         while self.match_types(
-            &[TokenType::STAR, TokenType::SLASH]
+            &[TokenType::STAR, TokenType::SLASH, TokenType::PERCENT]
         ) {
+            self.deeper()?;
             let operator = self.previous();
             let right = self.unary()?;
             expr = Expr::Binary(Binary{
@@ -349,59 +777,88 @@ impl Parser {
                 right: Box::new(right)
             })
         }
+        self.nesting = nesting;
         Ok(expr)
     }
 
 
 
-    fn unary(&mut self) -> Result<Expr, RuntimeError> {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.match_types(&[TokenType::BANG, TokenType::MINUS]) {
             let operator = self.previous();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             return Ok(Expr::Unary(Unary{operator, right: Box::new(right)}))
         }
-        return self.call()
+        return self.power()
+    }
+
+    // Binds tighter than unary on its left, so -2 ** 2 is -(2 ** 2), and
+    // recursing through unary on its right makes 2 ** 3 ** 2 be 2 ** (3 ** 2)
+    // and lets 2 ** -1 parse.
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.call()?;
+        if !self.match_types(&[TokenType::STAR_STAR]) {
+            return Ok(base);
+        }
+        let operator = self.previous();
+        let exponent = self.nested(Self::unary)?;
+        Ok(Expr::Binary(Binary{operator, left: Box::new(base), right: Box::new(exponent)}))
     }
 
-    fn call(&mut self) -> Result<Expr, RuntimeError> {
+    fn call(&mut self) -> Result<Expr, ParseError> {
         let mut callee = self.primary()?;
         // println!("current token: {:?}", self.peek());
 
+        let nesting = self.nesting;
         while true {
+            // `?.` goes in front of a call or an index, f?.(x) / xs?.[i]
+            let optional = if self.match_types(&[TokenType::QUESTION_DOT]) { Some(self.previous()) } else { None };
             if self.match_types(&[TokenType::LEFT_PAREN]) {
+                self.deeper()?;
                 let args: Vec<Expr> = self.finish_call()?;
                 let paren = self.previous();
-                callee = Expr::Call(Call{callee: Box::new(callee), paren: paren, arguments: args});
+                callee = Expr::Call(Call{callee: Box::new(callee), paren: paren, arguments: args, optional});
                 // println!("callee in func call: {callee:?}");
+            } else if self.match_types(&[TokenType::LEFT_BRACKET]) {
+                self.deeper()?;
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RIGHT_BRACKET, "expected ']' after index")?;
+                callee = Expr::Index(Index{object: Box::new(callee), bracket, index: Box::new(index), optional});
+            } else if optional.is_some() {
+                let token = self.peek().clone();
+                let mut err = ParseError::new(token.clone(), format!("expected '(' or '[' after '?.', found {}", describe(&token)));
+                if token.token_type == TokenType::IDENTIFIER {
+                    err.hint = Some(format!("there are no fields to read yet, a map entry is ?.[\"{}\"]", token.lexeme));
+                }
+                return Err(err);
             } else {
                 break
             }
         }
+        self.nesting = nesting;
         return Ok(callee);
     }
 
-    fn finish_call(&mut self) -> Result<Vec<Expr>, RuntimeError> {
-        // immutable borrow here if no .clone()?
-        let current = self.peek().clone();
+    fn finish_call(&mut self) -> Result<Vec<Expr>, ParseError> {
         let mut args: Vec<Expr> = Vec::new();
-        // println!("in finish_call");
-        while !self.check(TokenType::RIGHT_PAREN) {
-            // error if >= 255 args
-            if args.len() >= 255 {
-                return Err(RuntimeError{token: current.clone(), message: "too many arguments".to_string()})?;
-            }
-            args.push(self.expression()?);
-            if self.check(TokenType::COMMA) {
-                self.advance();
+        if !self.check(TokenType::RIGHT_PAREN) {
+            loop {
+                // error if >= 255 args
+                if args.len() >= 255 {
+                    return Err(ParseError::new(self.peek().clone(), "can't have more than 255 arguments".to_string()));
+                }
+                args.push(self.expression()?);
+                if !self.match_types(&[TokenType::COMMA]) {
+                    break;
+                }
             }
-            // println!("args in finish_call: {args:?}");
         }
-        self.consume(TokenType::RIGHT_PAREN, "Expected ')' after arguments")?;
+        self.consume(TokenType::RIGHT_PAREN, "expected ',' or ')' after argument")?;
 
         return Ok(args);
     }
 
-    fn primary(&mut self) -> Result<Expr, RuntimeError> {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         let current_token = self.peek();
         // println!("current_token in primary: {current_token:?}");
 
@@ -415,12 +872,47 @@ impl Parser {
                 Expr::Literal(Literal::Nil),
             Token{token_type: TokenType::NUMBER, ..} | Token{token_type: TokenType::STRING, ..} =>
                 Expr::Literal(current_token.literal.clone()),
+            Token{token_type: TokenType::LEFT_PAREN, ..} if self.starts_arrow_function() => {
+                self.advance();
+                return self.arrow_function()
+            }
+            Token{token_type: TokenType::FUN, ..} => {
+                self.advance();
+                return self.lambda()
+            }
             Token{token_type: TokenType::LEFT_PAREN, ..} => {
                 self.advance();  // past the '('
                 let expr = self.expression()?;
-                self.consume(TokenType::RIGHT_PAREN, "Expect ')' after expression.")?;
+                self.consume(TokenType::RIGHT_PAREN, "expected ')' after expression")?;
                 return Ok(Expr::Grouping(Grouping(Box::new(expr))))
             }
+            Token{token_type: TokenType::LEFT_BRACKET, ..} => {
+                let bracket = self.advance();
+                let mut elements = Vec::new();
+                // a trailing comma is fine, handy for lists split over lines
+                while !self.check(TokenType::RIGHT_BRACKET) {
+                    elements.push(self.expression()?);
+                    if !self.match_types(&[TokenType::COMMA]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RIGHT_BRACKET, "expected ',' or ']' after list element")?;
+                return Ok(Expr::List(ListExpr{bracket, elements}))
+            }
+            Token{token_type: TokenType::LEFT_BRACE, ..} => {
+                let brace = self.advance();
+                let mut entries = Vec::new();
+                while !self.check(TokenType::RIGHT_BRACE) {
+                    let key = self.expression()?;
+                    self.consume(TokenType::COLON, "expected ':' after map key")?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_types(&[TokenType::COMMA]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RIGHT_BRACE, "expected ',' or '}' after map entry")?;
+                return Ok(Expr::Map(MapExpr{brace, entries}))
+            }
 
             // We store a Expression::Variable that will point to (via environment) a
             // Variable object
@@ -431,7 +923,7 @@ impl Parser {
             _ => {
                 // println!("catch all not meant to happen!!");
                 // return Ok(Expr::Literal(Literal::String("aaah".to_string())))
-                return Err(RuntimeError{token: current_token.clone(), message: "primary unable to match".to_string()})?;
+                return Err(ParseError::new(current_token.clone(), format!("expected expression, found {}", describe(current_token))));
                 // return Ok(Expr::Literal(Literal::String("aaah".to_string())))
 
             }
@@ -445,16 +937,21 @@ impl Parser {
         // Expr::Literal(Literal::String(placeholder.clone()))
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, RuntimeError>{
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParseError>{
         if self.check(token_type) {
             // println!("consumed {token_type:?}");
             return Ok(self.advance())
         }
-        let token = self.peek();
-        // error(token, message);
-        // TODO why doesn't compile if comment out bottom?
-        // panic!("token: {token:?} {message}");
-        Err(RuntimeError{token: token.clone(), message: message.to_string()})
+        let token = self.peek().clone();
+        let previous = self.previous();
+        if token_type == TokenType::SEMICOLON && token.line > previous.line {
+            // almost certainly just forgotten at the end of the last line, so
+            // point there rather than at whatever starts the next one
+            let mut err = ParseError::new(previous.clone(), message.to_string());
+            err.hint = Some(format!("add ';' after '{}' at the end of line {}", previous.lexeme, previous.line));
+            return Err(err);
+        }
+        Err(ParseError::new(token.clone(), format!("{message}, found {}", describe(&token))))
     }
 
     // fn consume(&mut self, token_type: TokenType, message: &str) -> Token{
@@ -488,6 +985,11 @@ impl Parser {
         foo.token_type == token_type
     }
 
+    // is the token `distance` past the current one a `token_type`?
+    fn check_ahead(&self, distance: usize, token_type: TokenType) -> bool {
+        self.tokens.get(self.current as usize + distance).map(|token| token.token_type) == Some(token_type)
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -514,7 +1016,9 @@ mod test {
     use crate::lox::ast::{Binary, Expr, Grouping, Literal, Printer, Unary};
     use crate::token_type::{Token, TokenType};
     use crate::scanner::Scanner;
-    use crate::lox::ast::parser::Parser;
+    use crate::lox::ast::parser::{Parser, MAX_NESTING};
+    use crate::lox::ast::stack;
+    use crate::lox::ast::printer::DumpFormat;
 
     #[test]
     fn test_parser() {
@@ -526,7 +1030,7 @@ mod test {
         println!("tokens: {tokens:?}");
 
         let mut my_parser = Parser::new(tokens);
-        let expr = my_parser.parse_expr().unwrap();
+        let expr = my_parser.parse_lone_expr().unwrap();
         println!("expr: {expr:?}");
         // my_parser.advance();
         // let expr2 = my_parser.expression();
@@ -540,4 +1044,137 @@ mod test {
 
         assert!(3 == 3);
     }
+
+    #[test]
+    fn test_parse_errors_collected() {
+        // missing ')' in the if, bad var initializer, then a good statement
+        let source = String::from("if (x print 1; var y = ; print 2;");
+        let tokens = Scanner::new(source).scan_tokens();
+        let errors = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].token.lexeme, "print");
+        assert_eq!(errors[1].token.lexeme, ";");
+
+        let tokens = Scanner::new(String::from("while (true print 1;")).scan_tokens();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_error_messages() {
+        let parse_errors = |source: &str| Parser::new(Scanner::new(source.to_string()).scan_tokens()).parse().unwrap_err();
+
+        let errors = parse_errors("print (1 + );");
+        assert_eq!(errors[0].message, "expected expression, found ')'");
+
+        // missing ';' is reported against the end of the line before
+        let errors = parse_errors("var a = 1\nprint a;");
+        assert_eq!(errors[0].token.lexeme, "1");
+        assert_eq!(errors[0].hint.as_deref(), Some("add ';' after '1' at the end of line 1"));
+
+        let errors = parse_errors("fucn add(a, b) { print a + b; }");
+        assert_eq!(errors[0].hint.as_deref(), Some("did you mean 'fun'?"));
+        let errors = parse_errors("retrun x;");
+        assert_eq!(errors[0].hint.as_deref(), Some("did you mean 'return'?"));
+        let errors = parse_errors("whiel (x) { print x; }");
+        assert_eq!(errors[0].hint.as_deref(), Some("did you mean 'while'?"));
+        let errors = parse_errors("foo(1 2);");
+        assert_eq!(errors[0].message, "expected ',' or ')' after argument, found '2'");
+        assert_eq!(errors[0].hint, None);
+        let errors = parse_errors("outer: print 1;");
+        assert_eq!(errors[0].message, "expected a loop after label 'outer', found 'print'");
+        let errors = parse_errors("print m?.a;");
+        assert_eq!(errors[0].message, "expected '(' or '[' after '?.', found 'a'");
+        assert_eq!(errors[0].hint.as_deref(), Some("there are no fields to read yet, a map entry is ?.[\"a\"]"));
+        let errors = parse_errors("try { f(); } print 1;");
+        assert_eq!(errors[0].message, "expected 'catch' or 'finally' after try block, found 'print'");
+        let errors = parse_errors("for (x in xs) print x;");
+        assert_eq!(errors[0].message, "expected 'var' before the loop variable, found 'x'");
+        assert_eq!(errors[0].hint.as_deref(), Some("each time round is a new variable, write `for (var x in ...)`"));
+        let errors = parse_errors("xs?.[0] = 1;");
+        assert!(errors[0].message.starts_with("invalid assignment target"));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        // a debug build needs more stack for 200 levels than a test thread has
+        std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            stack::set_stack_size(64 << 20);
+            let parse = |source: String| Parser::new(Scanner::new(source).scan_tokens()).parse();
+            let nested = |open: &str, inner: &str, close: &str, n: usize| format!("{}{inner}{}", open.repeat(n), close.repeat(n));
+
+            assert!(parse(format!("print {};", nested("(", "1", ")", 150))).is_ok());
+            assert!(parse(format!("print 1{};", " + 1".repeat(150))).is_ok());
+            assert!(parse(format!("print {};", vec!["1 + 1"; 150].join(" == "))).is_ok());
+            for source in [
+                format!("print {};", nested("(", "1", ")", 300)),
+                format!("print {};", nested("[", "1", "]", 300)),
+                format!("print {}1;", "-".repeat(300)),
+                format!("var a; {}1;", "a = ".repeat(300)),
+                nested("{", "", "}", 300),
+                format!("{} 1;", "if (true)".repeat(300)),
+                format!("print 1{};", " + 1".repeat(300_000)),
+                format!("print a{};", "[0]".repeat(300_000)),
+                format!("f{};", "()".repeat(300)),
+                format!("print a{};", " or a * a".repeat(300)),
+            ] {
+                let errors = parse(source).unwrap_err();
+                assert_eq!(errors[0].message, format!("nested too deeply, the limit is {MAX_NESTING} levels"));
+            }
+        }).unwrap().join().unwrap();
+
+        // and on a thread the size std::thread::spawn makes, nobody having
+        // said otherwise, the stack runs low first
+        std::thread::Builder::new().stack_size(2 << 20).spawn(|| {
+            let source = format!("print {}1{};", "(".repeat(150), ")".repeat(150));
+            let errors = Parser::new(Scanner::new(source).scan_tokens()).parse().unwrap_err();
+            assert_eq!(errors[0].message, "nested too deeply for the stack there is");
+        }).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_map_literal_or_block() {
+        let parse = |source: &str| {
+            let stmts = Parser::new(Scanner::new(source.to_string()).scan_tokens()).parse().unwrap();
+            Printer.print_stmts(&stmts, DumpFormat::SExpr)
+        };
+        assert_eq!(parse("var m = {};"), "(var m map)\n");
+        assert_eq!(parse("{}"), "block\n");
+        assert_eq!(parse("{\"a\": [1, 2], (1): {}}[\"a\"];"), "(expr (index (map (: \"a\" (list 1 2)) (: (group 1) map)) \"a\"))\n");
+        // a ':' inside something nested doesn't make the block a map
+        assert_eq!(parse("{ f({\"a\": 1}); }"), "(block (expr (call var:f (map (: \"a\" 1)))))\n");
+        assert_eq!(parse("{ print 1; }"), "(block (print 1))\n");
+        // a labelled loop first thing in a block
+        assert_eq!(parse("{ a: while (x) break a; }"), "(block (a: while var:x break a))\n");
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let parse = |source: &str| {
+            let stmts = Parser::new(Scanner::new(source.to_string()).scan_tokens()).parse().unwrap();
+            Printer.print_stmts(&stmts, DumpFormat::SExpr)
+        };
+        assert_eq!(parse("a = b ? c : d ? e : f;"), "(expr (assign= var:a (?: var:b var:c (?: var:d var:e var:f))))\n");
+        assert_eq!(parse("a or b ? c = 1 : d;"), "(expr (?: (or var:a var:b) (assign= var:c 1) var:d))\n");
+        assert_eq!(parse("a + b % c * d;"), "(expr (+ var:a (* (% var:b var:c) var:d)))\n");
+        assert_eq!(parse("-a ** b ** -c;"), "(expr (- (** var:a (** var:b (- var:c)))))\n");
+        assert_eq!(parse("a[0] -= b += 2;"), "(expr (index-= var:a 0 (assign+= var:b 2)))\n");
+        // the ':' of a '?:' first thing in a block doesn't make it a map
+        assert_eq!(parse("{ a ? b : c; }"), "(block (expr (?: var:a var:b var:c)))\n");
+        assert_eq!(parse("a or b ?? c ?? d ? e : f;"), "(expr (?: (?? (?? (or var:a var:b) var:c) var:d) var:e var:f))\n");
+        assert_eq!(parse("a?.[0]?.(b)[1];"), "(expr (index (?.call (?.index var:a 0) var:b) 1))\n");
+        assert_eq!(parse("{ a ? b : c: d };"), "(expr (map (: (?: var:a var:b var:c) var:d)))\n");
+    }
+
+    #[test]
+    fn test_lambda_or_grouping() {
+        let parse = |source: &str| {
+            let stmts = Parser::new(Scanner::new(source.to_string()).scan_tokens()).parse().unwrap();
+            Printer.print_stmts(&stmts, DumpFormat::SExpr)
+        };
+        assert_eq!(parse("(a);"), "(expr (group var:a))\n");
+        assert_eq!(parse("(a) => a;"), "(expr (=> (a) var:a))\n");
+        assert_eq!(parse("() => (b) => b;"), "(expr (=> () (=> (b) var:b)))\n");
+        assert_eq!(parse("fun (x, y) { print x; };"), "(expr (fun (x, y) (print var:x)))\n");
+        assert_eq!(parse("fun f() {}"), "fun f()\n");
+    }
 }
\ No newline at end of file
@@ -1,71 +1,334 @@
 use crate::token_type::Token;
 
-use super::{Assign, Binary, Call, Expr, ExprVisitor, Grouping, Literal, Logical, Unary, Variable};
+use super::json;
+use super::{Assign, Binary, Call, Conditional, Expr, ExprVisitor, ForInStmt, ForStmt, FuncStmt, Grouping, IfStmt, Index, IndexAssign, Lambda, ListExpr, Literal, Logical, LoopJump, MapExpr, ReturnStmt, Stmt, StmtVisitor, ThrowStmt, TryStmt, Unary, Variable, WhileStmt};
+
+// How `Printer::print_stmts` lays the tree out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    SExpr,  // (* (- 123) (group 45.67))
+    Tree,   // indented, one node per line
+    Dot,    // Graphviz, pipe into `dot -Tpng`
+    Json,   // see json.rs for the schema
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sexpr" => Some(DumpFormat::SExpr),
+            "tree" => Some(DumpFormat::Tree),
+            "dot" => Some(DumpFormat::Dot),
+            "json" => Some(DumpFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// The visitors build one of these per AST node; the three formats are then
+// just different ways of walking it.
+pub struct Node {
+    label: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn leaf(label: &str) -> Self {
+        Node { label: label.to_string(), children: Vec::new() }
+    }
+
+    // -> "(label child[0] child[1] ... )", or just "label" for a leaf
+    fn sexpr(&self) -> String {
+        if self.children.is_empty() {
+            return self.label.clone();
+        }
+        let mut s = format!("({}", self.label);
+        for child in &self.children {
+            s.push_str(&format!(" {}", child.sexpr()));
+        }
+        s + ")"
+    }
+
+    fn tree(&self, prefix: &str, out: &mut String) {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i == self.children.len() - 1;
+            out.push_str(&format!("{}{}{}\n", prefix, if last { "└── " } else { "├── " }, child.label));
+            child.tree(&format!("{}{}", prefix, if last { "    " } else { "│   " }), out);
+        }
+    }
+
+    // writes this node and everything below it, returns this node's id
+    fn dot(&self, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        let label = self.label.replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!("    n{id} [label=\"{label}\"];\n"));
+        for child in &self.children {
+            let child_id = child.dot(next_id, out);
+            out.push_str(&format!("    n{id} -> n{child_id};\n"));
+        }
+        id
+    }
+}
 
 pub struct Printer;
 impl Printer {
     pub fn print(&mut self, expr: &Expr) -> String {
-        self.visit_expr(expr)
+        self.visit_expr(expr).sexpr()
     }
-    // -> "(name expr[0] expr[1] ... )"
-    fn parenthesize(&mut self, name: &str, expressions: Vec<&Expr>) -> String {
-        let mut s = format!("({}", name);
-        for expr in expressions {
-            s.push_str(&format!(" {}", &self.visit_expr(expr)));
+
+    pub fn print_stmt(&mut self, stmt: &Stmt) -> String {
+        self.visit_statement(stmt).sexpr()
+    }
+
+    pub fn print_stmts(&mut self, stmts: &Vec<Stmt>, format: DumpFormat) -> String {
+        if format == DumpFormat::Json {
+            return json::stmts_to_json(stmts);
         }
-        s + ")"
+        let nodes: Vec<Node> = stmts.iter().map(|stmt| self.visit_statement(stmt)).collect();
+        let mut out = String::new();
+        match format {
+            DumpFormat::SExpr => {
+                for node in nodes {
+                    out.push_str(&node.sexpr());
+                    out.push('\n');
+                }
+            }
+            DumpFormat::Tree => {
+                for node in nodes {
+                    out.push_str(&node.label);
+                    out.push('\n');
+                    node.tree("", &mut out);
+                }
+            }
+            DumpFormat::Dot => {
+                out.push_str("digraph ast {\n    node [shape=box];\n");
+                let mut next_id = 0;
+                for node in nodes {
+                    node.dot(&mut next_id, &mut out);
+                }
+                out.push_str("}\n");
+            }
+            DumpFormat::Json => unreachable!(),
+        }
+        out
+    }
+
+    // -> Node{name, [expr[0], expr[1], ...]}
+    fn parenthesize(&mut self, name: &str, expressions: Vec<&Expr>) -> Node {
+        let children = expressions.into_iter().map(|expr| self.visit_expr(expr)).collect();
+        Node { label: name.to_string(), children }
+    }
+
+    fn statements(&mut self, name: &str, statements: &[Stmt]) -> Node {
+        let children = statements.iter().map(|stmt| self.visit_statement(stmt)).collect();
+        Node { label: name.to_string(), children }
     }
 }
 
 // Printer is allowed to visit expressions
-impl ExprVisitor<String> for Printer {
-    fn visit_assignment(&mut self, assignment: &Assign) -> String {
-        self.parenthesize("assign=", vec![&Expr::Variable(assignment.name.clone()), &assignment.value])
+impl ExprVisitor<Node> for Printer {
+    fn visit_assignment(&mut self, assignment: &Assign) -> Node {
+        let name = format!("assign{}", assignment.operator.lexeme);
+        self.parenthesize(&name, vec![&Expr::Variable(assignment.name.clone()), &assignment.value])
     }
 
-    fn visit_logical(&mut self, logical: &Logical) -> String {
+    fn visit_conditional(&mut self, conditional: &Conditional) -> Node {
+        self.parenthesize("?:", vec![&conditional.condition, &conditional.then_branch, &conditional.else_branch])
+    }
+
+    fn visit_logical(&mut self, logical: &Logical) -> Node {
         self.parenthesize(&logical.operator.lexeme, vec![&logical.left, &logical.right])
     }
 
-    fn visit_binary(&mut self, binary: &Binary) -> String {
+    fn visit_binary(&mut self, binary: &Binary) -> Node {
         self.parenthesize(&binary.operator.lexeme, vec![&binary.left, &binary.right])
     }
 
-    fn visit_unary(&mut self, unary: &Unary) -> String {
+    fn visit_unary(&mut self, unary: &Unary) -> Node {
         self.parenthesize(&unary.operator.lexeme, vec![&unary.right])
     }
-    fn visit_call(&mut self, call: &Call) -> String {
-        let args: Vec<&Expr> = call.arguments.iter().collect();
-        self.parenthesize("func_call with args", args)
+    fn visit_call(&mut self, call: &Call) -> Node {
+        // (call callee arg[0] arg[1] ...), or (?.call ...) for f?.(...)
+        let mut exprs: Vec<&Expr> = vec![&call.callee];
+        exprs.extend(call.arguments.iter());
+        self.parenthesize(if call.optional.is_some() { "?.call" } else { "call" }, exprs)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) -> Node {
+        // (fun (a, b) stmts...), or (=> (a, b) expr) for an arrow
+        let parameters: Vec<&str> = lambda.parameters.iter().map(|p| p.lexeme.as_str()).collect();
+        match (&lambda.body[..], lambda.arrow) {
+            ([Stmt::Return(ReturnStmt { value, .. })], true) => {
+                self.parenthesize(&format!("=> ({})", parameters.join(", ")), vec![value])
+            }
+            _ => self.statements(&format!("fun ({})", parameters.join(", ")), &lambda.body),
+        }
+    }
+
+    fn visit_list(&mut self, list: &ListExpr) -> Node {
+        self.parenthesize("list", list.elements.iter().collect())
+    }
+
+    fn visit_map(&mut self, map: &MapExpr) -> Node {
+        // (map (: key value) ...)
+        let children = map.entries.iter().map(|(key, value)| self.parenthesize(":", vec![key, value])).collect();
+        Node { label: "map".to_string(), children }
     }
 
-    fn visit_grouping(&mut self, grouping: &Grouping) -> String {
+    fn visit_index(&mut self, index: &Index) -> Node {
+        self.parenthesize(if index.optional.is_some() { "?.index" } else { "index" }, vec![&index.object, &index.index])
+    }
+
+    fn visit_index_assign(&mut self, index_assign: &IndexAssign) -> Node {
+        let name = format!("index{}", index_assign.operator.lexeme);
+        self.parenthesize(&name, vec![&index_assign.object, &index_assign.index, &index_assign.value])
+    }
+
+    fn visit_grouping(&mut self, grouping: &Grouping) -> Node {
         self.parenthesize("group", vec![&grouping.0])
     }
 
-    fn visit_literal(&mut self, literal: &Literal) -> String {
+    fn visit_literal(&mut self, literal: &Literal) -> Node {
         match literal {
-            Literal::String(val) => val.clone(),
-            Literal::Number(val) => val.to_string(),
-            Literal::Nil => "nil".to_owned(),
-            Literal::Boolean(val) => val.to_string()
+            Literal::String(val) => Node::leaf(&format!("\"{val}\"")),
+            Literal::Number(val) => Node::leaf(&val.to_string()),
+            Literal::Nil => Node::leaf("nil"),
+            Literal::Boolean(val) => Node::leaf(&val.to_string())
+        }
+    }
+
+    fn visit_variable(&mut self, token: &Token) -> Node {
+        Node::leaf(&format!("var:{}", token.lexeme))
+    }
+
+    fn visit_null(&mut self) -> Node { Node::leaf("null") }
+}
+
+// ... and statements, so whole programs can be dumped
+impl StmtVisitor<Node> for Printer {
+    fn visit_expr_statement(&mut self, expr: &Expr) -> Node {
+        self.parenthesize("expr", vec![expr])
+    }
+
+    fn visit_print_statement(&mut self, expr: &Expr) -> Node {
+        self.parenthesize("print", vec![expr])
+    }
+
+    fn visit_block_statement(&mut self, statements: &Vec<Stmt>) -> Node {
+        self.statements("block", statements)
+    }
+
+    fn visit_while_statement(&mut self, while_stmt: &WhileStmt) -> Node {
+        let condition = self.visit_expr(&while_stmt.condition);
+        let body = self.visit_statement(&while_stmt.body);
+        Node { label: labelled(&while_stmt.label, "while"), children: vec![condition, body] }
+    }
+
+    fn visit_for_statement(&mut self, for_stmt: &ForStmt) -> Node {
+        // missing clauses show up as "none" so the positions stay fixed
+        let initializer = match &for_stmt.initializer {
+            Some(initializer) => self.visit_statement(initializer),
+            None => Node::leaf("none"),
+        };
+        let condition = match &for_stmt.condition {
+            Some(condition) => self.visit_expr(condition),
+            None => Node::leaf("none"),
+        };
+        let increment = match &for_stmt.increment {
+            Some(increment) => self.visit_expr(increment),
+            None => Node::leaf("none"),
+        };
+        let body = self.visit_statement(&for_stmt.body);
+        Node { label: labelled(&for_stmt.label, "for"), children: vec![initializer, condition, increment, body] }
+    }
+
+    fn visit_for_in_statement(&mut self, for_in: &ForInStmt) -> Node {
+        // (for-in x iterable body)
+        let children = vec![self.visit_expr(&for_in.iterable), self.visit_statement(&for_in.body)];
+        Node { label: labelled(&for_in.label, &format!("for-in {}", for_in.name.lexeme)), children }
+    }
+
+    fn visit_func_statement(&mut self, func_stmt: &FuncStmt) -> Node {
+        let parameters: Vec<&str> = func_stmt.parameters.iter().map(|p| p.lexeme.as_str()).collect();
+        self.statements(&format!("fun {}({})", func_stmt.name.lexeme, parameters.join(", ")), &func_stmt.body)
+    }
+
+    fn visit_var_statement(&mut self, var: &Variable) -> Node {
+        let name = format!("var {}", var.name.lexeme);
+        if let Expr::Null = var.initializer {
+            Node::leaf(&name)
+        } else {
+            self.parenthesize(&name, vec![&var.initializer])
         }
     }
 
-    fn visit_variable(&mut self, token: &Token) -> String {
-        format!("var:{}", token.lexeme).to_string()
+    fn visit_if_statement(&mut self, if_stmt: &IfStmt) -> Node {
+        let mut children = vec![self.visit_expr(&if_stmt.condition), self.visit_statement(&if_stmt.if_branch)];
+        if let Some(else_branch) = &if_stmt.else_branch {
+            children.push(self.visit_statement(else_branch));
+        }
+        Node { label: "if".to_string(), children }
     }
 
-    fn visit_null(&mut self) -> String { "null".to_string() }
+    fn visit_return_statement(&mut self, return_stmt: &ReturnStmt) -> Node {
+        if let Expr::Null = return_stmt.value {
+            Node::leaf("return")
+        } else {
+            self.parenthesize("return", vec![&return_stmt.value])
+        }
+    }
+
+    fn visit_break_statement(&mut self, jump: &LoopJump) -> Node {
+        Node::leaf(&jump_label(jump))
+    }
+
+    fn visit_continue_statement(&mut self, jump: &LoopJump) -> Node {
+        Node::leaf(&jump_label(jump))
+    }
+
+    fn visit_throw_statement(&mut self, throw: &ThrowStmt) -> Node {
+        self.parenthesize("throw", vec![&throw.value])
+    }
+
+    fn visit_try_statement(&mut self, try_stmt: &TryStmt) -> Node {
+        // (try (block ...) (catch e ...) (finally ...)), leaving out a
+        // missing clause
+        let mut children = vec![self.statements("block", &try_stmt.body)];
+        if let Some(catch) = &try_stmt.catch {
+            children.push(self.statements(&format!("catch {}", catch.name.lexeme), &catch.body));
+        }
+        if let Some(finally) = &try_stmt.finally {
+            children.push(self.statements("finally", finally));
+        }
+        Node { label: "try".to_string(), children }
+    }
+}
+
+// "outer: while" for a labelled loop
+fn labelled(label: &Option<Token>, name: &str) -> String {
+    match label {
+        Some(label) => format!("{}: {name}", label.lexeme),
+        None => name.to_string(),
+    }
+}
+
+// "break" / "continue outer"
+fn jump_label(jump: &LoopJump) -> String {
+    match &jump.label {
+        Some(label) => format!("{} {}", jump.keyword.lexeme, label.lexeme),
+        None => jump.keyword.lexeme.clone(),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::lox::
         ast::{Binary, Expr, Grouping, Literal, Unary};
+    use crate::lox::ast::parser::Parser;
+    use crate::scanner::Scanner;
     use crate::token_type::{Token, TokenType};
 
-    use super::Printer;
+    use super::{DumpFormat, Printer};
 
     #[test]
     fn test_ast_printer() {
@@ -83,4 +346,25 @@ mod test {
         println!("{out}");
         assert!(out == "(* (- 123) (group 45.67))")
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stmt_printer_formats() {
+        let source = "fun f(a) { print a; } if (x) f(1); else { var y; }".to_string();
+        let tokens = Scanner::new(source).scan_tokens();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let mut printer = Printer;
+
+        assert_eq!(
+            printer.print_stmts(&stmts, DumpFormat::SExpr),
+            "(fun f(a) (print var:a))\n(if var:x (expr (call var:f 1)) (block var y))\n"
+        );
+        assert_eq!(
+            printer.print_stmts(&stmts, DumpFormat::Tree),
+            "fun f(a)\n└── print\n    └── var:a\nif\n├── var:x\n├── expr\n│   └── call\n│       ├── var:f\n│       └── 1\n└── block\n    └── var y\n"
+        );
+        let dot = printer.print_stmts(&stmts, DumpFormat::Dot);
+        assert!(dot.starts_with("digraph ast {"));
+        assert!(dot.contains("n0 [label=\"fun f(a)\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+    }
+}
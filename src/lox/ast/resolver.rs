@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use crate::token_type::{Literal, Token};
+
+use super::{Assign, Binary, Call, Conditional, Expr, ExprVisitor, ForInStmt, ForStmt, FuncStmt, Grouping, IfStmt, Index, IndexAssign, Lambda, ListExpr, Logical, LoopJump, MapExpr, ReturnStmt, Stmt, StmtVisitor, ThrowStmt, TryStmt, Unary, Variable, WhileStmt};
+
+// Static checks that run between parsing and interpreting, for mistakes that
+// are wrong whatever values turn up at runtime. `jlox check` stops here.
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    pub token: Token,
+    pub message: String,
+}
+
+pub struct Resolver {
+    // one map per block / function / for scope, name -> finished defining it?
+    // Globals aren't tracked, redefining those is allowed.
+    scopes: Vec<HashMap<String, bool>>,
+    // how many function bodies we're inside, `return` needs at least one
+    function_depth: usize,
+    // the loops around us in the current function, innermost last, with
+    // their labels. break / continue need one.
+    loops: Vec<Option<Token>>,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self { scopes: Vec::new(), function_depth: 0, loops: Vec::new(), errors: Vec::new() }
+    }
+
+    pub fn resolve(mut self, stmts: &Vec<Stmt>) -> Result<(), Vec<ResolveError>> {
+        for stmt in stmts {
+            self.visit_statement(stmt);
+        }
+        self.finish()
+    }
+
+    // a lone expression, like the REPL echoes. It can still hold a lambda
+    // with a stray `break` or `return` in it.
+    pub fn resolve_expr(mut self, expr: &Expr) -> Result<(), Vec<ResolveError>> {
+        self.visit_expr(expr);
+        self.finish()
+    }
+
+    fn finish(self) -> Result<(), Vec<ResolveError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors.push(ResolveError { token: token.clone(), message });
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else { return };
+        if scope.insert(name.lexeme.clone(), false).is_some() {
+            self.error(name, format!("'{}' is already declared in this scope", name.lexeme));
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    fn resolve_loop_body(&mut self, label: &Option<Token>, body: &Stmt) {
+        if let Some(label) = label {
+            let taken = self.loops.iter().flatten().any(|outer| outer.lexeme == label.lexeme);
+            if taken {
+                self.error(label, format!("the label '{}' is already used by an enclosing loop", label.lexeme));
+            }
+        }
+        self.loops.push(label.clone());
+        self.visit_statement(body);
+        self.loops.pop();
+    }
+
+    fn resolve_jump(&mut self, jump: &LoopJump) {
+        match &jump.label {
+            _ if self.loops.is_empty() => {
+                self.error(&jump.keyword, format!("can't use '{}' outside a loop", jump.keyword.lexeme));
+            }
+            Some(label) if !self.loops.iter().flatten().any(|outer| outer.lexeme == label.lexeme) => {
+                self.error(label, format!("there's no enclosing loop labelled '{}'", label.lexeme));
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_function(&mut self, parameters: &Vec<Token>, body: &Vec<Stmt>) {
+        // a loop outside the function can't be broken out of from inside it
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        self.function_depth += 1;
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter);
+            self.define(parameter);
+        }
+        self.resolve_block(body);
+        self.end_scope();
+        self.function_depth -= 1;
+        self.loops = enclosing_loops;
+    }
+
+    fn resolve_block(&mut self, statements: &Vec<Stmt>) {
+        for stmt in statements {
+            self.visit_statement(stmt);
+        }
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_assignment(&mut self, assignment: &Assign) {
+        self.visit_expr(&assignment.value);
+    }
+
+    fn visit_conditional(&mut self, conditional: &Conditional) {
+        self.visit_expr(&conditional.condition);
+        self.visit_expr(&conditional.then_branch);
+        self.visit_expr(&conditional.else_branch);
+    }
+
+    fn visit_logical(&mut self, logical: &Logical) {
+        self.visit_expr(&logical.left);
+        self.visit_expr(&logical.right);
+    }
+
+    fn visit_binary(&mut self, binary: &Binary) {
+        self.visit_expr(&binary.left);
+        self.visit_expr(&binary.right);
+    }
+
+    fn visit_unary(&mut self, unary: &Unary) {
+        self.visit_expr(&unary.right);
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        self.visit_expr(&call.callee);
+        for argument in &call.arguments {
+            self.visit_expr(argument);
+        }
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        self.resolve_function(&lambda.parameters, &lambda.body);
+    }
+
+    fn visit_list(&mut self, list: &ListExpr) {
+        for element in &list.elements {
+            self.visit_expr(element);
+        }
+    }
+
+    fn visit_map(&mut self, map: &MapExpr) {
+        for (key, value) in &map.entries {
+            self.visit_expr(key);
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_index(&mut self, index: &Index) {
+        self.visit_expr(&index.object);
+        self.visit_expr(&index.index);
+    }
+
+    fn visit_index_assign(&mut self, index_assign: &IndexAssign) {
+        self.visit_expr(&index_assign.object);
+        self.visit_expr(&index_assign.index);
+        self.visit_expr(&index_assign.value);
+    }
+
+    fn visit_grouping(&mut self, grouping: &Grouping) {
+        self.visit_expr(&grouping.0);
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal) {}
+
+    fn visit_variable(&mut self, token: &Token) {
+        // `var a = a;` in a local scope: the new `a` exists but has no value yet
+        if self.scopes.last().and_then(|scope| scope.get(&token.lexeme)) == Some(&false) {
+            self.error(token, format!("can't read local variable '{}' in its own initializer", token.lexeme));
+        }
+    }
+
+    fn visit_null(&mut self) {}
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_expr_statement(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_print_statement(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    fn visit_block_statement(&mut self, statements: &Vec<Stmt>) {
+        self.begin_scope();
+        self.resolve_block(statements);
+        self.end_scope();
+    }
+
+    fn visit_while_statement(&mut self, while_stmt: &WhileStmt) {
+        self.visit_expr(&while_stmt.condition);
+        self.resolve_loop_body(&while_stmt.label, &while_stmt.body);
+    }
+
+    fn visit_for_statement(&mut self, for_stmt: &ForStmt) {
+        // same scope Interp gives the initializer's variable
+        self.begin_scope();
+        if let Some(initializer) = &for_stmt.initializer {
+            self.visit_statement(initializer);
+        }
+        if let Some(condition) = &for_stmt.condition {
+            self.visit_expr(condition);
+        }
+        if let Some(increment) = &for_stmt.increment {
+            self.visit_expr(increment);
+        }
+        self.resolve_loop_body(&for_stmt.label, &for_stmt.body);
+        self.end_scope();
+    }
+
+    fn visit_for_in_statement(&mut self, for_in: &ForInStmt) {
+        self.visit_expr(&for_in.iterable);
+        self.begin_scope();
+        self.declare(&for_in.name);
+        self.define(&for_in.name);
+        self.resolve_loop_body(&for_in.label, &for_in.body);
+        self.end_scope();
+    }
+
+    fn visit_func_statement(&mut self, func_stmt: &FuncStmt) {
+        // defined straight away so the function can call itself
+        self.declare(&func_stmt.name);
+        self.define(&func_stmt.name);
+
+        self.resolve_function(&func_stmt.parameters, &func_stmt.body);
+    }
+
+    fn visit_return_statement(&mut self, return_stmt: &ReturnStmt) {
+        if self.function_depth == 0 {
+            self.error(&return_stmt.keyword, "can't return from top-level code".to_string());
+        }
+        self.visit_expr(&return_stmt.value);
+    }
+
+    fn visit_break_statement(&mut self, jump: &LoopJump) {
+        self.resolve_jump(jump);
+    }
+
+    fn visit_continue_statement(&mut self, jump: &LoopJump) {
+        self.resolve_jump(jump);
+    }
+
+    fn visit_throw_statement(&mut self, throw: &ThrowStmt) {
+        self.visit_expr(&throw.value);
+    }
+
+    fn visit_try_statement(&mut self, try_stmt: &TryStmt) {
+        self.visit_block_statement(&try_stmt.body);
+        if let Some(catch) = &try_stmt.catch {
+            self.begin_scope();
+            self.declare(&catch.name);
+            self.define(&catch.name);
+            self.resolve_block(&catch.body);
+            self.end_scope();
+        }
+        if let Some(finally) = &try_stmt.finally {
+            self.visit_block_statement(finally);
+        }
+    }
+
+    fn visit_var_statement(&mut self, var: &Variable) {
+        self.declare(&var.name);
+        self.visit_expr(&var.initializer);
+        self.define(&var.name);
+    }
+
+    fn visit_if_statement(&mut self, if_stmt: &IfStmt) {
+        self.visit_expr(&if_stmt.condition);
+        self.visit_statement(&if_stmt.if_branch);
+        if let Some(else_branch) = &if_stmt.else_branch {
+            self.visit_statement(else_branch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lox::ast::parser::Parser;
+    use crate::scanner::Scanner;
+
+    use super::Resolver;
+
+    fn resolve(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        match Resolver::new().resolve(&stmts) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| format!("{}: {}", e.token.line, e.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_resolver() {
+        // globals can be redeclared and read themselves
+        assert!(resolve("var a = 1; var a = a + 1;").is_empty());
+        assert!(resolve("{ var a = 1; { var a = a; } }").len() == 1);
+        assert_eq!(
+            resolve("{\n var a = 1;\n var a = 2;\n}\nfun f(x, x) {}"),
+            vec![
+                "3: 'a' is already declared in this scope".to_string(),
+                "5: 'x' is already declared in this scope".to_string(),
+            ]
+        );
+        assert_eq!(resolve("for (var i = 0; i < 3; i = i + 1) { var i = i; }").len(), 1);
+        assert_eq!(resolve("fun f() { return 1; }\nreturn 2;"), vec!["2: can't return from top-level code".to_string()]);
+    }
+
+    #[test]
+    fn test_try_catch_scopes() {
+        assert!(resolve("try { var e = 1; } catch (e) { print e; } finally { var e = 2; }").is_empty());
+        assert_eq!(resolve("try {} catch (e) {\n var e;\n}"), vec!["2: 'e' is already declared in this scope".to_string()]);
+        assert_eq!(resolve("while (true) { try { break; } finally { continue; } }"), Vec::<String>::new());
+        assert_eq!(resolve("try { break; } finally {}"), vec!["1: can't use 'break' outside a loop".to_string()]);
+    }
+
+    #[test]
+    fn test_loop_jumps() {
+        assert!(resolve("while (true) { if (x) break; continue; }").is_empty());
+        assert!(resolve("outer: for (;;) for (var x in xs) { continue outer; }").is_empty());
+        assert_eq!(
+            resolve("break;\nwhile (true) { fun f() { continue; } }\nouter: while (true) { break inner; }"),
+            vec![
+                "1: can't use 'break' outside a loop".to_string(),
+                "2: can't use 'continue' outside a loop".to_string(),
+                "3: there's no enclosing loop labelled 'inner'".to_string(),
+            ]
+        );
+        assert_eq!(
+            resolve("a: while (true) { a: while (true) {} }\na: while (true) {}"),
+            vec!["1: the label 'a' is already used by an enclosing loop".to_string()]
+        );
+
+        let expr = Parser::new(Scanner::new("(fun () { break; })()".to_string()).scan_tokens()).parse_lone_expr().unwrap();
+        let errors = Resolver::new().resolve_expr(&expr).unwrap_err();
+        assert_eq!(errors[0].message, "can't use 'break' outside a loop");
+    }
+}
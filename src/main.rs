@@ -1,170 +1,476 @@
-use std::io;
-use std::io::Write;
 use std::env;
+use std::io::Read;
 use std::sync::LazyLock;
 use std::sync::Mutex;
+use std::time::Duration;
 
 
 mod token_type;
 use token_type::TokenType;
 use token_type::Token;
-use token_type::Literal;
 
 mod lox;
-use lox::ast::{Expr, Binary, parser};
+use lox::ast::{Stmt, parser, formatter, json, stack};
+use lox::ast::json::Json;
+use lox::ast::resolver::{ResolveError, Resolver};
+use lox::ast::parser::ParseError;
+use lox::ast::printer::{DumpFormat, Printer};
+use lox::ast::formatter::FormatError;
 use lox::ast::interpreter::{RuntimeError, Interp};
+use lox::ast::natives::Permissions;
 mod scanner;
+use scanner::ScanError;
+mod repl;
 
 static mut HAD_ERROR: bool = false;
 static mut HAD_RUNTIME_ERROR: bool = false;
+static mut DIAGNOSTICS: Diagnostics = Diagnostics::Human;
 static my_interpreter: LazyLock<Mutex<Interp>> = LazyLock::new(|| {
     let interp = Interp::new();
     Mutex::new(interp)
 });
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    dbg!(&args);
-    println!("args length: {}", args.len());
-
-    
-    let word = "Prog";
-    println!("{}", word.chars().nth(3).unwrap());
+const USAGE: &str = "\
+Usage: jlox [options] [command]
+
+Commands:
+    run <file> [args...]    run a script (plain `jlox <file>` works too)
+    repl                    the interactive prompt, the default
+    tokens <file>           print what the scanner makes of a file
+    ast <file>              print the syntax tree, see --format
+    check <file>            parse and resolve, but don't run
+    fmt <files...>          format source files, see `jlox fmt`
+    -e <code> [args...]     run <code> given on the command line
+
+<file> can be - to read from stdin. The script gets [args...] from args();
+put them after -- if they look like options.
+
+Options:
+    --backend=ast                   what runs the program (the tree-walker is the only one so far)
+    --trace                         print each statement to stderr as it runs
+    --diagnostics=human|json        how errors are reported, json is one object per line
+    --format=sexpr|tree|dot|json    the layout `ast` prints
+    --dump-ast[=<format>] <file>    the old spelling of `ast [--format=<format>] <file>`
+    --json                          `run` a JSON syntax tree (from `ast --format=json`) instead of source
+    --sandbox                       no files, environment variables, stdin, args() or exit() for the script
+    --seed=<n>                      seed random() etc., same as calling seed(n) first
+    --max-depth=<n>                 how deeply calls can nest before a \"Stack overflow\" error (default 1000)
+    --fuel=<n>                      stop with \"Out of fuel\" after running <n> statements and expressions
+    --timeout=<ms>                  stop with \"Timed out\" once the script has run for <ms> milliseconds
+                                    (both of these are for each entry in the REPL)
+    --max-memory=<mb>               stop with \"Out of memory\" if the script's values take up more than about <mb> MB
+";
+
+const COMMANDS: &[&str] = &["run", "repl", "tokens", "ast", "check", "fmt"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Diagnostics {
+    Human,
+    Json,
+}
 
-    println!("token type: {:?}", TokenType::LEFT_PAREN);
-    println!("{}", 1.0/0.0);
+// Room for --max-depth calls and the parser's MAX_NESTING levels, which a
+// debug build especially goes through quickly (see stack.rs). Only what's
+// used gets touched.
+const STACK_SIZE: usize = 256 << 20;
 
+fn main() {
+    let cli = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(cli_main).unwrap();
+    if cli.join().is_err() {
+        // the panic has been printed already
+        std::process::exit(101);
+    }
+}
 
-    // let boo: Binary = Binary {
-    //     left: Box::new(Expr),
-    //     right: Box::new(Expr),
-    //     operator: Token()
-    // };
+fn cli_main() {
+    stack::set_stack_size(STACK_SIZE);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut trace = false;
+    let mut from_json = false;
+    let mut format = DumpFormat::SExpr;
+    let mut permissions = Permissions::all();
+    let mut seed = None;
+    let mut max_depth = None;
+    let mut fuel = None;
+    let mut timeout = None;
+    let mut max_memory = None;
+    let mut rest: Vec<&str> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return;
+            }
+            "--trace" => trace = true,
+            "--json" => from_json = true,
+            "--sandbox" => permissions = Permissions::none(),
+            "--" => {
+                // everything after this is for the script
+                rest.extend(args.by_ref().map(String::as_str));
+            }
+            "-e" => {
+                // whatever comes next is code, even if it starts with '-'
+                rest.push("-e");
+                match args.next() {
+                    Some(code) => rest.push(code),
+                    None => usage_error("-e needs some code to run"),
+                }
+            }
+            arg if arg.starts_with("--backend=") => {
+                let name = &arg["--backend=".len()..];
+                if name != "ast" {
+                    usage_error(&format!("unknown backend '{name}', the only one is 'ast'"));
+                }
+            }
+            arg if arg.starts_with("--diagnostics=") => set_diagnostics(&arg["--diagnostics=".len()..]),
+            arg if arg.starts_with("--seed=") => {
+                let n = &arg["--seed=".len()..];
+                match n.parse::<f64>() {
+                    Ok(n) => seed = Some(n),
+                    Err(_) => usage_error(&format!("--seed wants a number, not '{n}'")),
+                }
+            }
+            arg if arg.starts_with("--max-depth=") => {
+                let n = &arg["--max-depth=".len()..];
+                match n.parse::<usize>() {
+                    Ok(n) if n > 0 => max_depth = Some(n),
+                    _ => usage_error(&format!("--max-depth wants a whole number above 0, not '{n}'")),
+                }
+            }
+            arg if arg.starts_with("--fuel=") => {
+                let n = &arg["--fuel=".len()..];
+                match n.parse::<u64>() {
+                    Ok(n) => fuel = Some(n),
+                    Err(_) => usage_error(&format!("--fuel wants a whole number, not '{n}'")),
+                }
+            }
+            arg if arg.starts_with("--timeout=") => {
+                let ms = &arg["--timeout=".len()..];
+                match ms.parse::<u64>() {
+                    Ok(ms) => timeout = Some(Duration::from_millis(ms)),
+                    Err(_) => usage_error(&format!("--timeout wants a whole number of milliseconds, not '{ms}'")),
+                }
+            }
+            arg if arg.starts_with("--max-memory=") => {
+                let mb = &arg["--max-memory=".len()..];
+                match mb.parse::<usize>() {
+                    Ok(mb) if mb > 0 => max_memory = Some(mb.saturating_mul(1 << 20)),
+                    _ => usage_error(&format!("--max-memory wants a whole number of MB above 0, not '{mb}'")),
+                }
+            }
+            arg if arg.starts_with("--format=") => format = ast_format(&arg["--format=".len()..]),
+            // from before there were subcommands, still in people's scripts
+            "--dump-ast" => rest.push("ast"),
+            arg if arg.starts_with("--dump-ast=") => {
+                format = ast_format(&arg["--dump-ast=".len()..]);
+                rest.push("ast");
+            }
+            arg if arg.starts_with("--") => usage_error(&format!("unknown option {arg}")),
+            // fmt has options of its own, everything after it is for run_fmt
+            "fmt" if rest.is_empty() => {
+                let fmt_args: Vec<String> = args.by_ref().cloned().collect();
+                run_fmt(&fmt_args);
+                return;
+            }
+            arg => rest.push(arg),
+        }
+    }
 
+    let mut interpreter = my_interpreter.lock().unwrap();
+    interpreter.set_trace(trace);
+    interpreter.set_permissions(permissions);
+    if let Some(seed) = seed {
+        interpreter.set_seed(seed.to_bits());
+    }
+    if let Some(max_depth) = max_depth {
+        interpreter.set_max_call_depth(max_depth);
+    }
+    interpreter.set_fuel(fuel);
+    interpreter.set_memory_limit(max_memory);
+    // counted from here, so parsing the script is part of its time
+    interpreter.set_deadline(timeout);
+    let script_args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+    match rest.as_slice() {
+        ["run", _, args @ ..] | ["-e", _, args @ ..] => interpreter.set_args(script_args(args)),
+        [path, args @ ..] if !COMMANDS.contains(path) => interpreter.set_args(script_args(args)),
+        _ => {}
+    }
+    drop(interpreter);
+
+    match rest.as_slice() {
+        [] | ["repl"] => repl::run_prompt(repl::EntryLimits { fuel, timeout }),
+        ["run", path, ..] if from_json => run_json(path),
+        ["run", path, ..] => run_file(path),
+        ["tokens", path] => dump_tokens(path),
+        ["ast", path] => dump_ast(path, format),
+        ["check", path] => check(path),
+        ["-e", code, ..] => {
+            run(code);
+            exit_on_error();
+        }
+        [path, ..] if !COMMANDS.contains(path) => run_file(path),
+        _ => usage_error("wrong number of arguments"),
+    }
+}
 
-    let token = Token::new(
-        TokenType::STRING,
-        "example".to_string(),
-        // Some(Literal::String("hello".to_string())),
-        Literal::String("hello".to_string()),
-        1,
-    );
+fn ast_format(name: &str) -> DumpFormat {
+    match DumpFormat::from_name(name) {
+        Some(format) => format,
+        None => usage_error(&format!("unknown AST format '{name}'")),
+    }
+}
 
-    println!("token: {:?}", token.to_string());
+fn set_diagnostics(name: &str) {
+    let diagnostics = match name {
+        "human" => Diagnostics::Human,
+        "json" => Diagnostics::Json,
+        name => usage_error(&format!("unknown diagnostics format '{name}'")),
+    };
+    unsafe { DIAGNOSTICS = diagnostics };
+}
 
+fn usage_error(message: &str) -> ! {
+    eprintln!("jlox: {message}\n\n{USAGE}");
+    std::process::exit(64);
+}
 
-    if args.len() > 2 {
-        println!("Usage: jlox [script]");
-        std::process::exit(1);
-    } else if args.len() == 2 {
-        run_file(args[1].clone());
+// The whole file, or all of stdin for "-".
+fn read_source(path: &str) -> String {
+    let result = if path == "-" {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents).map(|_| contents)
     } else {
-        run_prompt();
+        std::fs::read_to_string(path)
+    };
+    match result {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("jlox: can't read {path}: {err}");
+            std::process::exit(66);
+        }
     }
+}
 
+fn exit_on_error() {
+    unsafe {
+        if HAD_ERROR {
+            std::process::exit(65);
+        }
+        if HAD_RUNTIME_ERROR {
+            std::process::exit(70);
+        }
+    }
+}
 
+fn run_file(path: &str) {
+    run(&read_source(path));
+    exit_on_error();
+}
 
-    // println!("Guess the number!");
-
-    // let secret_number = rand::thread_rng().gen_range(1..=100);
-
-    // // println!("The secret number is {secret_number}");
-
-
-    // loop {
-    //     println!("Please input your guess.");
+// jlox tokens <file>
+fn dump_tokens(path: &str) {
+    if let Some(tokens) = scan(&read_source(path)) {
+        for token in tokens {
+            println!("{:4} {}", token.line, token.to_string());
+        }
+    }
+    exit_on_error();
+}
 
-    //     let mut guess: String = String::new();
+// jlox check <file>
+// Everything up to running it, so editors and CI can catch mistakes.
+fn check(path: &str) {
+    if let Some(stmts) = scan(&read_source(path)).and_then(parse) {
+        resolve(&stmts);
+    }
+    exit_on_error();
+}
 
-    //     io::stdin()
-    //         .read_line(&mut guess)
-    //         .expect("Failed to read line");
+const FMT_USAGE: &str = "Usage: jlox [--diagnostics=human|json] fmt [--check | --write] <files...>";
+
+// jlox fmt [--check | --write] <files...>
+// Prints the formatted source by default. --check lists the files that aren't
+// formatted and exits with 1 (for CI), --write rewrites them in place. A file
+// of - is stdin, which --write prints instead. --diagnostics can go before or
+// after the `fmt`.
+fn run_fmt(args: &[String]) {
+    let mut check = false;
+    let mut write = false;
+    let mut paths: Vec<&str> = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{FMT_USAGE}");
+                return;
+            }
+            "--check" => check = true,
+            "--write" | "-w" => write = true,
+            arg if arg.starts_with("--diagnostics=") => set_diagnostics(&arg["--diagnostics=".len()..]),
+            "-" => paths.push("-"),
+            arg if arg.starts_with('-') => usage_error(&format!("unknown fmt option {arg}")),
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() || (check && write) {
+        println!("{FMT_USAGE}");
+        std::process::exit(64);
+    }
 
-    //     // let guess: u32 = guess.trim().parse().expect("Please type a number!");
-    //     let guess: u32 = match guess.trim().parse() {
-    //         Ok(num) => num,
-    //         Err(_) => continue,
-    //     };
+    let mut unformatted = false;
+    for path in paths {
+        let contents = read_source(path);
+        let formatted = match formatter::format_source(&contents) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                match errors {
+                    FormatError::Scan(errors) => report_scan_errors(&errors),
+                    FormatError::Parse(errors) => report_parse_errors(&errors),
+                    FormatError::Comment(line) => {
+                        let message = "comment inside an expression or parameter list, move it before or after the statement";
+                        if diagnostics() == Diagnostics::Json {
+                            json_diagnostic("format", line, None, message, None);
+                        } else {
+                            error(line, message);
+                        }
+                    }
+                }
+                eprintln!("{path}: not formatted, fix the errors above first");
+                std::process::exit(65);
+            }
+        };
+        if check {
+            if formatted != contents {
+                println!("{path}");
+                unformatted = true;
+            }
+        } else if write && path != "-" {
+            if formatted != contents {
+                if let Err(err) = std::fs::write(path, formatted) {
+                    eprintln!("jlox: can't write {path}: {err}");
+                    std::process::exit(74);
+                }
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
 
+// jlox ast [--format=sexpr|tree|dot|json] <file>
+// Parses the file and prints its syntax tree instead of running it.
+fn dump_ast(path: &str, format: DumpFormat) {
+    if let Some(stmts) = scan(&read_source(path)).and_then(parse) {
+        print!("{}", Printer.print_stmts(&stmts, format));
+    }
+    exit_on_error();
+}
 
-    //     println!("You guessed: {}", guess);
+// jlox run --json <file>
+// Runs a program given as a JSON AST (e.g. from `ast --format=json`) instead of source.
+fn run_json(path: &str) {
+    let contents = read_source(path);
+    let stmts = match json::stmts_from_json(&contents) {
+        Ok(stmts) => stmts,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            std::process::exit(65);
+        }
+    };
+    // a tool can write out anything, e.g. a `return` at the top level
+    if !resolve(&stmts) {
+        exit_on_error();
+    }
+    let _ = my_interpreter.lock().unwrap().interpret_stmts(&stmts);
+    unsafe {
+        if HAD_RUNTIME_ERROR {
+            std::process::exit(70);
+        }
+    }
+}
 
-    //     match guess.cmp(&secret_number) {
-    //         Ordering::Less => println!("Too small!"),
-    //         Ordering::Greater => println!("Too big!"),
-    //         Ordering::Equal => {
-    //             println!("You win!");
-    //             break;
-    //         },
-    //     }
-    // }
+// What `run_with` shows besides the program's own output.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub dump_tokens: bool,
+    pub dump_ast: bool,
+    // print the value of a source that's just one expression (REPL)
+    pub echo: bool,
+}
 
+fn run(source: &str) {
+    run_with(source, &RunOptions::default());
 }
 
-fn run_file(path: String) {
-    let contents = std::fs::read_to_string(&path).expect("Should have read the file!");
-    // println!("runFile read {path} and got:\n{contents}!");
+fn run_with(source: &str, options: &RunOptions) {
+    let Some(tokens) = scan(source) else { return };
+    if options.dump_tokens {
+        for token in &tokens {
+            println!("{:?}", token);
+        }
+    }
 
-    run(&contents);
-    unsafe{
-    if HAD_ERROR {
-        std::process::exit(65);
+    if options.echo {
+        if let Some(expr) = parser::Parser::new(tokens.clone()).parse_lone_expr() {
+            if options.dump_ast {
+                println!("{}", Printer.print(&expr));
+            }
+            if !resolved(Resolver::new().resolve_expr(&expr)) {
+                return;
+            }
+            my_interpreter.lock().unwrap().interpret(&expr);
+            return;
+        }
     }
-    if HAD_RUNTIME_ERROR {
-        std::process::exit(70);
+
+    // don't run half a program
+    let Some(stmts) = parse(tokens) else { return };
+    if options.dump_ast {
+        print!("{}", Printer.print_stmts(&stmts, DumpFormat::SExpr));
     }
+    if !resolve(&stmts) {
+        return;
     }
+    let _ = my_interpreter.lock().unwrap().interpret_stmts(&stmts);
 }
 
-fn run_prompt() {
-    loop {
-        print!("> ");
-        io::stdout().flush().expect("aaaah");
-        let mut repl_input: String = String::new();
-
-        match io::stdin()
-            .read_line(&mut repl_input)
-            {
-                Ok(0) => {
-                    break;
-                }
-                Ok(_) => {
-                    run(&repl_input);
-                }
-                Err(error) => {
-                    eprintln!("IDK??");
-                }
-            }
-        // println!("You entered: {repl_input}");
+// The front end, one stage at a time. Each reports its own errors and
+// gives back None (or false) if there were any.
+fn scan(source: &str) -> Option<Vec<Token>> {
+    let mut my_scanner = scanner::Scanner::new(source.to_string());
+    let tokens = my_scanner.scan_tokens();
+    if !my_scanner.errors().is_empty() {
+        report_scan_errors(&my_scanner.errors());
+        return None;
     }
+    Some(tokens)
 }
 
-fn run(source: &String) {
-    // println!("{line}");
-    let mut S = scanner::Scanner::new(source.clone());
-
-    let tokens: Vec<Token> = S.scan_tokens();
-    for token in &tokens {
-        println!("{:?}", token);
+fn parse(tokens: Vec<Token>) -> Option<Vec<Stmt>> {
+    match parser::Parser::new(tokens).parse() {
+        Ok(stmts) => Some(stmts),
+        Err(errors) => {
+            report_parse_errors(&errors);
+            None
+        }
     }
+}
 
-    let mut my_parser = parser::Parser::new(tokens);
-    // let expr = my_parser.parse().unwrap();
-
-    // unsafe {
-    //     my_interpreter.interpret(&expr);
-    // }
+fn resolve(stmts: &Vec<Stmt>) -> bool {
+    resolved(Resolver::new().resolve(stmts))
+}
 
-    let stmts = my_parser.parse();
-    // unsafe {
-    //     my_interpreter.interpret_stmts(&stmts);
-    //     // let my_interp = LazyLock::<Interp>::get_mut(&mut my_interpreter).unwrap();
-    //     // my_interp.interpret_stmts(&stmts);
-        
-    //     // interpret_stmts(&stmts)
-    // }
-    unsafe {
-        my_interpreter.lock().unwrap().interpret_stmts(&stmts);
+fn resolved(result: Result<(), Vec<ResolveError>>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(errors) => {
+            report_resolve_errors(&errors);
+            false
+        }
     }
 }
 
@@ -173,12 +479,90 @@ pub fn error(line: usize, message: &str) {
     report(line, "", message);
 }
 
+fn report_scan_errors(errors: &Vec<ScanError>) {
+    for err in errors {
+        if diagnostics() == Diagnostics::Json {
+            json_diagnostic("scan", err.line, None, &err.message, None);
+        } else {
+            error(err.line, &err.message);
+        }
+    }
+    unsafe { HAD_ERROR = true };
+}
+
+fn report_parse_errors(errors: &Vec<ParseError>) {
+    for err in errors {
+        if diagnostics() == Diagnostics::Json {
+            json_diagnostic("parse", err.token.line, Some(&err.token), &err.message, err.hint.as_deref());
+            continue;
+        }
+        lox::error(&err.token, &err.message);
+        if let Some(hint) = &err.hint {
+            println!("    hint: {hint}");
+        }
+    }
+    unsafe { HAD_ERROR = true };
+}
+
+fn report_resolve_errors(errors: &Vec<ResolveError>) {
+    for err in errors {
+        if diagnostics() == Diagnostics::Json {
+            json_diagnostic("resolve", err.token.line, Some(&err.token), &err.message, None);
+        } else {
+            report(err.token.line, &format!(" at '{}'", err.token.lexeme), &err.message);
+        }
+    }
+    unsafe { HAD_ERROR = true };
+}
+
+fn diagnostics() -> Diagnostics {
+    unsafe { DIAGNOSTICS }
+}
+
+// --diagnostics=json: one object per line on stderr, e.g.
+// {"kind":"parse","line":3,"at":"}","message":"expected expression, found '}'"}
+fn json_diagnostic(kind: &str, line: usize, token: Option<&Token>, message: &str, hint: Option<&str>) {
+    let fields = diagnostic_fields(kind, line, token, message, hint);
+    eprintln!("{}", Json::Object(fields).compact());
+}
+
+fn diagnostic_fields(kind: &str, line: usize, token: Option<&Token>, message: &str, hint: Option<&str>) -> Vec<(String, Json)> {
+    let mut fields = vec![
+        ("kind".to_string(), Json::String(kind.to_string())),
+        ("line".to_string(), Json::Number(line as f64)),
+    ];
+    if let Some(token) = token {
+        let at = if token.token_type == TokenType::EOF { "end" } else { &token.lexeme };
+        fields.push(("at".to_string(), Json::String(at.to_string())));
+    }
+    fields.push(("message".to_string(), Json::String(message.to_string())));
+    if let Some(hint) = hint {
+        fields.push(("hint".to_string(), Json::String(hint.to_string())));
+    }
+    fields
+}
+
 fn report(line: usize, location: &str, message: &str) {
     eprintln!("[line {}] Error{}: {}", line, location, message);
 }
 
 // error whilst interpreting? RuntimeError contains both a token and a message.
 fn runtime_error(err: RuntimeError) {
-    println!("{err:?}\n[line {}]", err.token.line);
+    let traceback = err.traceback();
+    if diagnostics() == Diagnostics::Json {
+        // a limit is hit wherever the script had got to, not at a token
+        let token = if err.limit.is_some() { None } else { Some(&err.token) };
+        let mut fields = diagnostic_fields("runtime", err.token.line, token, &err.message, None);
+        let traceback = traceback.into_iter().map(Json::String).collect();
+        fields.push(("traceback".to_string(), Json::Array(traceback)));
+        eprintln!("{}", Json::Object(fields).compact());
+        unsafe { HAD_RUNTIME_ERROR = true };
+        return;
+    }
+    // the message, then where it happened from the innermost call out
+    println!("{}", err.message);
+    for line in traceback {
+        println!("{line}");
+    }
     unsafe { HAD_RUNTIME_ERROR = true };
 }
\ No newline at end of file
@@ -0,0 +1,629 @@
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::lox::ast::interpreter::stringify;
+use crate::lox::ast::parser::Parser;
+use crate::scanner::Scanner;
+use crate::token_type::RESERVED_KEYWORDS;
+use crate::{my_interpreter, run_with, RunOptions, HAD_ERROR};
+
+// kept in the user's home directory, one line per entry
+const HISTORY_FILE: &str = ".lox_history";
+const MAX_HISTORY: usize = 1000;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+const COMMANDS: &[&str] = &[":tokens", ":ast", ":env", ":load", ":reset", ":quit", ":help"];
+const HELP: &str = "\
+:tokens        toggle printing the tokens of each entry
+:ast           toggle printing the AST of each entry
+:env           show the variables that are defined
+:load <file>   run a file in this session
+:reset         forget everything that's been defined
+:quit          leave (so does ctrl-d)";
+
+// --fuel and --timeout, which in the REPL are for each entry rather than
+// the whole session: one that runs out doesn't leave the rest with nothing,
+// and time spent typing doesn't count.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EntryLimits {
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl EntryLimits {
+    // before each entry or :load
+    fn renew(&self) {
+        let mut interpreter = my_interpreter.lock().unwrap();
+        interpreter.set_fuel(self.fuel);
+        interpreter.set_deadline(self.timeout);
+    }
+}
+
+// The interactive prompt. Lines are collected until they make a complete
+// entry (see is_incomplete), and each entry runs in the same Interp, so
+// variables and functions stick around between entries. An entry that's
+// just an expression gets its value printed.
+pub fn run_prompt(limits: EntryLimits) {
+    let mut editor = LineEditor::new(history_path());
+    let mut options = RunOptions { echo: true, ..Default::default() };
+    'entries: loop {
+        let mut source = String::new();
+        let mut prompt = PROMPT;
+        loop {
+            editor.set_completions(completions());
+            let line = match editor.read_line(prompt) {
+                Input::Line(line) => line,
+                Input::Interrupted => continue 'entries,  // ctrl-c throws the entry away
+                Input::Eof => break 'entries,
+            };
+            if source.is_empty() && line.trim().is_empty() {
+                continue 'entries;
+            }
+            editor.add_history(&line);
+            if source.is_empty() && line.trim_start().starts_with(':') {
+                if !run_command(line.trim(), &mut options, &limits) {
+                    break 'entries;
+                }
+                continue 'entries;
+            }
+            source.push_str(&line);
+            source.push('\n');
+            // a blank line pushes an unfinished entry through anyway, so the
+            // user gets to see the parse error instead of being stuck
+            if line.trim().is_empty() || !is_incomplete(&source) {
+                break;
+            }
+            prompt = CONTINUATION_PROMPT;
+        }
+        unsafe { HAD_ERROR = false };
+        limits.renew();
+        run_with(&source, &options);
+    }
+}
+
+// Runs a meta-command, returns false for :quit.
+fn run_command(line: &str, options: &mut RunOptions, limits: &EntryLimits) -> bool {
+    let (command, argument) = match line.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, ""),
+    };
+    match command {
+        ":tokens" => {
+            options.dump_tokens = !options.dump_tokens;
+            println!("tokens {}", if options.dump_tokens { "on" } else { "off" });
+        }
+        ":ast" => {
+            options.dump_ast = !options.dump_ast;
+            println!("ast {}", if options.dump_ast { "on" } else { "off" });
+        }
+        ":env" => {
+            for (name, value) in my_interpreter.lock().unwrap().bindings() {
+                println!("{name} = {}", stringify(&value));
+            }
+        }
+        ":load" if argument.is_empty() => println!("usage: :load <file>"),
+        ":load" => match std::fs::read_to_string(argument) {
+            Ok(contents) => {
+                unsafe { HAD_ERROR = false };
+                limits.renew();
+                // a file isn't an entry, don't echo a lone expression in it
+                run_with(&contents, &RunOptions { echo: false, ..options.clone() });
+            }
+            Err(err) => println!("can't read {argument}: {err}"),
+        },
+        ":reset" => {
+            my_interpreter.lock().unwrap().reset();
+            println!("environment cleared");
+        }
+        ":quit" => return false,
+        ":help" => println!("{HELP}"),
+        _ => println!("unknown command {command}, try :help"),
+    }
+    true
+}
+
+// What tab can complete to: keywords, whatever's defined, the natives and
+// the commands.
+fn completions() -> Vec<String> {
+    let mut words: Vec<String> = RESERVED_KEYWORDS.keys().map(|k| k.to_string()).collect();
+    let interpreter = my_interpreter.lock().unwrap();
+    words.extend(interpreter.bindings().into_iter().map(|(name, _)| name));
+    words.extend(interpreter.native_names());
+    words.extend(COMMANDS.iter().map(|c| c.to_string()));
+    words.sort();
+    words.dedup();
+    words
+}
+
+// `1 + 2` with no ';' is a whole entry too, it just gets echoed
+fn is_lone_expression(source: &str) -> bool {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens();
+    scanner.errors().is_empty() && Parser::new(tokens).parse_lone_expr().is_some()
+}
+
+// Whether `source` still needs more lines: an unclosed '(', '{' or '[', an
+// unterminated string, or a last statement with no ';' or '}' yet.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut depth: i32 = 0;
+    let mut last = None;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(_) => {}
+                        None => return true,
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                // comment, up to the end of the line
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            }
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            last = Some(c);
+        }
+    }
+    // depth < 0 is a syntax error, not something more input could fix
+    if depth > 0 {
+        return true;
+    }
+    !matches!(last, None | Some(';') | Some('}')) && !is_lone_expression(source)
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+pub enum Input {
+    Line(String),
+    Interrupted,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    KillLine,   // ctrl-u
+    Interrupt,  // ctrl-c
+    Eof,        // ctrl-d
+    Other,
+}
+
+// what the caller should do after a key
+#[derive(Debug, PartialEq)]
+enum Action {
+    Redraw,
+    Submit(String),
+    // more than one way to complete, list them under the line
+    ShowCompletions(Vec<String>),
+    Interrupt,
+    Eof,
+}
+
+// The line being edited plus the history to scroll through. Kept apart from
+// the terminal handling so the key handling can be tested.
+struct EditorState {
+    buffer: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    // == history.len() while editing a fresh line
+    history_index: usize,
+    // the fresh line, put back when scrolling down past the newest entry
+    draft: Vec<char>,
+    completions: Vec<String>,
+}
+
+impl EditorState {
+    fn new(history: Vec<String>) -> Self {
+        let history_index = history.len();
+        Self { buffer: Vec::new(), cursor: 0, history, history_index, draft: Vec::new(), completions: Vec::new() }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.cursor = 0;
+        self.history_index = self.history.len();
+        self.draft.clear();
+    }
+
+    fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    fn show_history(&mut self, index: usize) {
+        if self.history_index == self.history.len() {
+            self.draft = self.buffer.clone();
+        }
+        self.history_index = index;
+        self.buffer = if index == self.history.len() {
+            self.draft.clone()
+        } else {
+            self.history[index].chars().collect()
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    fn complete(&mut self) -> Action {
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_' || *c == ':';
+        let start = self.buffer[..self.cursor].iter().rposition(|c| !is_word(c)).map_or(0, |i| i + 1);
+        let word: String = self.buffer[start..self.cursor].iter().collect();
+        if word.is_empty() {
+            return Action::Redraw;
+        }
+        let matches: Vec<&String> = self.completions.iter().filter(|c| c.starts_with(&word)).collect();
+        if matches.is_empty() {
+            return Action::Redraw;
+        }
+        // longest prefix all the matches share
+        let mut common: Vec<char> = matches[0].chars().collect();
+        for candidate in &matches[1..] {
+            let shared = common.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+            common.truncate(shared);
+        }
+        let rest = &common[word.chars().count()..];
+        if rest.is_empty() && matches.len() > 1 {
+            return Action::ShowCompletions(matches.into_iter().cloned().collect());
+        }
+        for &c in rest {
+            self.buffer.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+        Action::Redraw
+    }
+
+    fn handle_key(&mut self, key: Key) -> Action {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Submit(self.line()),
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Delete => {
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.buffer.len(),
+            Key::Tab => return self.complete(),
+            Key::Up => {
+                if self.history_index > 0 {
+                    self.show_history(self.history_index - 1);
+                }
+            }
+            Key::Down => {
+                if self.history_index < self.history.len() {
+                    self.show_history(self.history_index + 1);
+                }
+            }
+            Key::KillLine => {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Interrupt => return Action::Interrupt,
+            Key::Eof => {
+                // like a shell: ctrl-d only quits on an empty line
+                if self.buffer.is_empty() {
+                    return Action::Eof;
+                }
+            }
+            Key::Other => {}
+        }
+        Action::Redraw
+    }
+}
+
+pub struct LineEditor {
+    state: EditorState,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = history_path.as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(|line| line.to_string()).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            if let Some(path) = &history_path {
+                let _ = std::fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        Self { state: EditorState::new(history), history_path }
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        let history = &mut self.state.history;
+        if line.trim().is_empty() || history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        history.push(line.to_string());
+        if history.len() > MAX_HISTORY {
+            history.remove(0);
+        }
+        // appended straight away so nothing is lost if the session dies
+        if let Some(path) = &self.history_path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+    }
+
+    pub fn set_completions(&mut self, completions: Vec<String>) {
+        self.state.completions = completions;
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> Input {
+        if io::stdin().is_terminal() {
+            if let Some(_raw) = RawMode::enable() {
+                return self.edit_line(prompt);
+            }
+        }
+
+        // piped input (or no stty): no editing, just lines
+        print!("{prompt}");
+        io::stdout().flush().expect("aaaah");
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => Input::Eof,
+            Ok(_) => Input::Line(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+
+    fn edit_line(&mut self, prompt: &str) -> Input {
+        self.state.reset();
+        let mut stdin = io::stdin().lock();
+        self.redraw(prompt);
+        loop {
+            let key = match read_key(&mut stdin) {
+                Ok(key) => key,
+                Err(_) => Key::Eof,
+            };
+            match self.state.handle_key(key) {
+                Action::Redraw => self.redraw(prompt),
+                Action::ShowCompletions(words) => {
+                    print!("\r\n{}\r\n", words.join("  "));
+                    self.redraw(prompt);
+                }
+                Action::Submit(line) => {
+                    print!("\r\n");
+                    io::stdout().flush().expect("aaaah");
+                    return Input::Line(line);
+                }
+                Action::Interrupt => {
+                    print!("^C\r\n");
+                    io::stdout().flush().expect("aaaah");
+                    return Input::Interrupted;
+                }
+                Action::Eof => {
+                    print!("\r\n");
+                    io::stdout().flush().expect("aaaah");
+                    return Input::Eof;
+                }
+            }
+        }
+    }
+
+    fn redraw(&self, prompt: &str) {
+        // back to column 0, prompt + line, clear the rest, then move the
+        // cursor back to where it belongs
+        let line = self.state.line();
+        let mut out = format!("\r{prompt}{line}\x1b[K");
+        let behind = self.state.buffer.len() - self.state.cursor;
+        if behind > 0 {
+            out.push_str(&format!("\x1b[{behind}D"));
+        }
+        print!("{out}");
+        io::stdout().flush().expect("aaaah");
+    }
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    if input.read(&mut byte)? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(byte[0])
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let byte = read_byte(input)?;
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,       // ctrl-a
+        0x05 => Key::End,        // ctrl-e
+        0x03 => Key::Interrupt,  // ctrl-c
+        0x04 => Key::Eof,        // ctrl-d
+        0x15 => Key::KillLine,   // ctrl-u
+        0x09 => Key::Tab,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            // put multi-byte UTF-8 characters back together
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    })
+}
+
+// arrow keys etc. arrive as ESC [ A, ESC [ 3 ~, ESC O H, ...
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let kind = read_byte(input)?;
+    if kind != b'[' && kind != b'O' {
+        return Ok(Key::Other);
+    }
+    let mut code = read_byte(input)?;
+    let mut number = String::new();
+    while code.is_ascii_digit() {
+        number.push(code as char);
+        code = read_byte(input)?;
+    }
+    Ok(match (code, number.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) => Key::Home,
+        (b'F', _) => Key::End,
+        (b'~', "1" | "7") => Key::Home,
+        (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Other,
+    })
+}
+
+// Puts the terminal into raw mode (no echo, no line buffering) for as long as
+// it's alive. Goes through `stty` so we don't need a terminal crate.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Some(RawMode { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_incomplete, read_key, Action, EditorState, Key};
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("fun add(a, b) {\n"));
+        assert!(is_incomplete("print add(1,\n"));
+        assert!(is_incomplete("var xs = [\n  1,\n"));
+        assert!(is_incomplete("print \"multi\nline"));
+        assert!(is_incomplete("print 1 + 2\n"));
+        assert!(is_incomplete("var x = 1\n"));
+        assert!(!is_incomplete("1 + 2\n"));
+        assert!(!is_incomplete("x = 3\n"));
+        assert!(!is_incomplete("print 1 + 2;\n"));
+        assert!(!is_incomplete("fun f() {\n  print \"}\";\n}\n"));
+        assert!(!is_incomplete("var x = 1; // trailing ( comment\n"));
+        assert!(!is_incomplete("}\n"));
+        assert!(!is_incomplete("var xs = [\n  1,\n];\n"));
+    }
+
+    #[test]
+    fn test_line_editing_and_history() {
+        let mut state = EditorState::new(vec!["print 1;".to_string(), "print 2;".to_string()]);
+        for c in "pint".chars() {
+            state.handle_key(Key::Char(c));
+        }
+        // go back and fix the typo
+        state.handle_key(Key::Home);
+        state.handle_key(Key::Right);
+        state.handle_key(Key::Char('r'));
+        assert_eq!(state.line(), "print");
+
+        state.handle_key(Key::Up);
+        assert_eq!(state.line(), "print 2;");
+        state.handle_key(Key::Up);
+        state.handle_key(Key::Up);
+        assert_eq!(state.line(), "print 1;");
+        state.handle_key(Key::Down);
+        state.handle_key(Key::Down);
+        assert_eq!(state.line(), "print");  // the draft comes back
+
+        state.handle_key(Key::Backspace);
+        assert_eq!(state.handle_key(Key::Enter), Action::Submit("prin".to_string()));
+        assert_eq!(state.handle_key(Key::Eof), Action::Redraw);
+    }
+
+    #[test]
+    fn test_tab_completion() {
+        let mut state = EditorState::new(Vec::new());
+        state.completions = vec!["counter".to_string(), "count_down".to_string(), "print".to_string()];
+        for c in "pr".chars() {
+            state.handle_key(Key::Char(c));
+        }
+        state.handle_key(Key::Tab);
+        assert_eq!(state.line(), "print");
+
+        state.handle_key(Key::Char(' '));
+        state.handle_key(Key::Char('c'));
+        // both start with "count", that much goes in
+        assert_eq!(state.handle_key(Key::Tab), Action::Redraw);
+        assert_eq!(state.line(), "print count");
+        assert_eq!(
+            state.handle_key(Key::Tab),
+            Action::ShowCompletions(vec!["counter".to_string(), "count_down".to_string()])
+        );
+        state.handle_key(Key::Char('e'));
+        state.handle_key(Key::Tab);
+        assert_eq!(state.line(), "print counter");
+    }
+
+    #[test]
+    fn test_read_key_escapes() {
+        let mut input: &[u8] = b"\x1b[A\x1b[3~\x1bOHx\xc3\xa9\r";
+        let keys: Vec<Key> = (0..6).map(|_| read_key(&mut input).unwrap()).collect();
+        assert_eq!(keys, vec![Key::Up, Key::Delete, Key::Home, Key::Char('x'), Key::Char('é'), Key::Enter]);
+    }
+}
@@ -1,25 +1,38 @@
 use crate::token_type::TokenType;
 use crate::token_type::Token;
 use crate::token_type::Literal;
+use crate::token_type::Comment;
 use crate::token_type::RESERVED_KEYWORDS;
-use crate::error;
+
+// Collected rather than printed straight away, so callers decide how (and
+// whether) to report them - the REPL scans speculatively, for example.
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    pub line: usize,
+    pub message: String,
+}
 
 pub struct Scanner {
-    source: String,
+    // indexed by char, not byte, so start/current never split a character
+    source: Vec<char>,
     tokens: Vec<Token>,
+    comments: Vec<Comment>,
     start: usize,
     current: usize,
     line: usize,
+    errors: Vec<ScanError>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self{
         Scanner {
-            source,
+            source: source.chars().collect(),
             tokens: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
-            line: 1
+            line: 1,
+            errors: Vec::new(),
         }
     }
 
@@ -38,6 +51,21 @@ impl Scanner {
         self.tokens.clone()
     }
 
+    // Only populated after scan_tokens.
+    pub fn comments(&self) -> Vec<Comment> {
+        self.comments.clone()
+    }
+
+    // Also only populated after scan_tokens. Tokens are still produced around
+    // the bad characters, but they shouldn't be run.
+    pub fn errors(&self) -> Vec<ScanError> {
+        self.errors.clone()
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(ScanError { line: self.line, message });
+    }
+
     fn is_at_end(&self) -> bool {
         self.current > self.source.len()
     }
@@ -49,12 +77,51 @@ impl Scanner {
                 ')' => self.add_token(TokenType::RIGHT_PAREN),
                 '{' => self.add_token(TokenType::LEFT_BRACE),
                 '}' => self.add_token(TokenType::RIGHT_BRACE),
+                '[' => self.add_token(TokenType::LEFT_BRACKET),
+                ']' => self.add_token(TokenType::RIGHT_BRACKET),
                 ',' => self.add_token(TokenType::COMMA),
+                ':' => self.add_token(TokenType::COLON),
                 '.' => self.add_token(TokenType::DOT),
-                '-' => self.add_token(TokenType::MINUS),
-                '+' => self.add_token(TokenType::PLUS),
                 ';' => self.add_token(TokenType::SEMICOLON),
-                '*' => self.add_token(TokenType::STAR),
+                '?' => {
+                    if self.match_next('?') {
+                        self.add_token(TokenType::QUESTION_QUESTION);
+                    } else if self.match_next('.') {
+                        self.add_token(TokenType::QUESTION_DOT);
+                    } else {
+                        self.add_token(TokenType::QUESTION);
+                    }
+                },
+                '-' => {
+                    if self.match_next('=') {
+                        self.add_token(TokenType::MINUS_EQUAL);
+                    } else {
+                        self.add_token(TokenType::MINUS);
+                    }
+                },
+                '+' => {
+                    if self.match_next('=') {
+                        self.add_token(TokenType::PLUS_EQUAL);
+                    } else {
+                        self.add_token(TokenType::PLUS);
+                    }
+                },
+                '%' => {
+                    if self.match_next('=') {
+                        self.add_token(TokenType::PERCENT_EQUAL);
+                    } else {
+                        self.add_token(TokenType::PERCENT);
+                    }
+                },
+                '*' => {
+                    if self.match_next('*') {
+                        self.add_token(TokenType::STAR_STAR);
+                    } else if self.match_next('=') {
+                        self.add_token(TokenType::STAR_EQUAL);
+                    } else {
+                        self.add_token(TokenType::STAR);
+                    }
+                },
                 '!' => {
                     if self.match_next('=') {
                         self.add_token(TokenType::BANG_EQUAL);
@@ -65,6 +132,8 @@ impl Scanner {
                 '=' => {
                     if self.match_next('=') {
                         self.add_token(TokenType::EQUAL_EQUAL);
+                    } else if self.match_next('>') {
+                        self.add_token(TokenType::ARROW);
                     } else {
                         self.add_token(TokenType::EQUAL);
                     }
@@ -86,16 +155,14 @@ impl Scanner {
 
                 '/' => {
                     if self.match_next('/') {
-                        // comment - we skip until we hit a newline
-                        // while self.peek() != '\n' && !self.isAtEnd() {
-                        //     self.advance();
-                        // }
-                        while let Some(c) = self.advance() {
-                            if c == '\n' {
-                                self.line += 1;
-                                break;
-                            }
+                        // comment - we skip until we hit a newline (left for
+                        // the '\n' arm to count), keeping the text around.
+                        while self.peek() != '\n' && self.peek() != '\0' {
+                            self.advance();
                         }
+                        self.add_comment();
+                    } else if self.match_next('=') {
+                        self.add_token(TokenType::SLASH_EQUAL);
                     } else {
                         self.add_token(TokenType::SLASH);
                     }
@@ -116,7 +183,7 @@ impl Scanner {
                     } else if Scanner::is_alpha(c){
                         self.identifier();
                     } else {
-                        error(self.line, &format!("Unexpected character '{}'.", c))
+                        self.error(format!("Unexpected character '{}'.", c));
                     }
                 }
             }
@@ -147,7 +214,7 @@ impl Scanner {
     }
 
     fn current_to_string(&self) -> String {
-        self.source[self.start..self.current].to_string()
+        self.source[self.start..self.current].iter().collect()
     }
 
 
@@ -179,7 +246,8 @@ impl Scanner {
         }
 
         if self.is_at_end() {
-            error(self.line, "Unterminated string.");
+            self.error("Unterminated string.".to_string());
+            return;
         }
 
         // the closing ".
@@ -187,7 +255,7 @@ impl Scanner {
 
         // start is at " so + 1, and current is at "+1, so -1 to get the contents
         //"my_string" -> my_string
-        let value: String = self.source[self.start+1..self.current-1].to_string();
+        let value: String = self.source[self.start+1..self.current-1].iter().collect();
         // println!("{value}, {0}, {1}", self.start, self.current);
         self.add_token2(TokenType::STRING, Some(Literal::String(value)));
         
@@ -196,7 +264,7 @@ impl Scanner {
     // this is like a conditional advance!
     fn match_next(&mut self, expected: char) -> bool{
         if self.is_at_end() { return false};
-        if self.source.chars().nth(self.current) != Some(expected) { return false; }
+        if self.source.get(self.current) != Some(&expected) { return false; }
         
         // otherwise, advance current and return true!
         self.current += 1;
@@ -210,7 +278,7 @@ impl Scanner {
         // unwrap_or is a safe way to get the character at the current position,
         // or return '\0' if we're at the end
         // which should be impossible because we check isAtEnd() first
-        self.source.chars().nth(self.current).unwrap_or('\0')
+        self.source.get(self.current).copied().unwrap_or('\0')
     }
 
     fn peek_next(&mut self) -> char {
@@ -225,11 +293,20 @@ impl Scanner {
     }
     
     fn advance(&mut self) -> Option<char> {
-        let my_char = self.source.chars().nth(self.current);
+        let my_char = self.source.get(self.current).copied();
         self.current += 1;
         my_char
     }
 
+    fn add_comment(&mut self) {
+        let trailing = self.tokens.last().is_some_and(|token| token.line == self.line);
+        self.comments.push(Comment {
+            text: self.current_to_string().trim_end().to_string(),
+            line: self.line,
+            trailing,
+        });
+    }
+
     fn add_token(&mut self, token_type: TokenType) {
         self.add_token2(token_type, None)
     }
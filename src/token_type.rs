@@ -2,47 +2,87 @@ use once_cell;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use crate::lox::ast::LoxCallable;
+use crate::lox::ast::map::LoxMap;
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub(crate) enum TokenType {
   // Single-character tokens.
   LEFT_PAREN, RIGHT_PAREN, LEFT_BRACE, RIGHT_BRACE,
-  COMMA, DOT, MINUS, PLUS, SEMICOLON, SLASH, STAR,
+  LEFT_BRACKET, RIGHT_BRACKET,
+  COMMA, COLON, DOT, MINUS, PLUS, SEMICOLON, SLASH, STAR, PERCENT, QUESTION,
 
   // One or two character tokens.
   BANG, BANG_EQUAL,
-  EQUAL, EQUAL_EQUAL,
+  EQUAL, EQUAL_EQUAL, ARROW,
   GREATER, GREATER_EQUAL,
   LESS, LESS_EQUAL,
+  STAR_STAR, QUESTION_QUESTION, QUESTION_DOT,
+  PLUS_EQUAL, MINUS_EQUAL, STAR_EQUAL, SLASH_EQUAL, PERCENT_EQUAL,
 
   // Literals.
   IDENTIFIER, STRING, NUMBER,
 
   // Keywords.
-  AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR,
-  PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE,
+  AND, BREAK, CATCH, CLASS, CONTINUE, ELSE, FALSE, FINALLY, FUN, FOR, IF, IN, NIL, OR,
+  PRINT, RETURN, SUPER, THIS, THROW, TRUE, TRY, VAR, WHILE,
 
   // operators
 
   EOF
 }
 
+// Every TokenType, so one can be looked up by its name (see from_name).
+// Keep in sync with the enum above.
+const ALL_TOKEN_TYPES: &[TokenType] = &[
+  TokenType::LEFT_PAREN, TokenType::RIGHT_PAREN, TokenType::LEFT_BRACE, TokenType::RIGHT_BRACE,
+  TokenType::LEFT_BRACKET, TokenType::RIGHT_BRACKET,
+  TokenType::COMMA, TokenType::COLON, TokenType::DOT, TokenType::MINUS, TokenType::PLUS, TokenType::SEMICOLON,
+  TokenType::SLASH, TokenType::STAR, TokenType::PERCENT, TokenType::QUESTION,
+  TokenType::BANG, TokenType::BANG_EQUAL, TokenType::EQUAL, TokenType::EQUAL_EQUAL, TokenType::ARROW,
+  TokenType::GREATER, TokenType::GREATER_EQUAL, TokenType::LESS, TokenType::LESS_EQUAL,
+  TokenType::STAR_STAR, TokenType::QUESTION_QUESTION, TokenType::QUESTION_DOT,
+  TokenType::PLUS_EQUAL, TokenType::MINUS_EQUAL, TokenType::STAR_EQUAL, TokenType::SLASH_EQUAL, TokenType::PERCENT_EQUAL,
+  TokenType::IDENTIFIER, TokenType::STRING, TokenType::NUMBER,
+  TokenType::AND, TokenType::BREAK, TokenType::CATCH, TokenType::CLASS, TokenType::CONTINUE, TokenType::ELSE, TokenType::FALSE,
+  TokenType::FINALLY, TokenType::FUN, TokenType::FOR, TokenType::IF, TokenType::IN, TokenType::NIL, TokenType::OR, TokenType::PRINT,
+  TokenType::RETURN, TokenType::SUPER, TokenType::THIS, TokenType::THROW, TokenType::TRUE, TokenType::TRY, TokenType::VAR,
+  TokenType::WHILE,
+  TokenType::EOF,
+];
+
+impl TokenType {
+    // inverse of the Debug name, e.g. "EQUAL_EQUAL" -> EQUAL_EQUAL
+    pub fn from_name(name: &str) -> Option<TokenType> {
+        ALL_TOKEN_TYPES.iter().find(|token_type| format!("{token_type:?}") == name).copied()
+    }
+}
+
 pub(crate) static RESERVED_KEYWORDS: Lazy<HashMap<&str, TokenType>> = Lazy::new(|| {
     HashMap::from([
         ("and", TokenType::AND),
+        ("break", TokenType::BREAK),
+        ("catch", TokenType::CATCH),
         ("class", TokenType::CLASS),
+        ("continue", TokenType::CONTINUE),
         ("else", TokenType::ELSE),
         ("false", TokenType::FALSE),
+        ("finally", TokenType::FINALLY),
         ("for", TokenType::FOR),
         ("fun", TokenType::FUN),
         ("if", TokenType::IF),
+        ("in", TokenType::IN),
         ("nil", TokenType::NIL),
         ("or", TokenType::OR),
         ("print", TokenType::PRINT),
         ("return", TokenType::RETURN),
         ("super", TokenType::SUPER),
         ("this", TokenType::THIS),
+        ("throw", TokenType::THROW),
         ("true", TokenType::TRUE),
+        ("try", TokenType::TRY),
         ("var", TokenType::VAR),
         ("while", TokenType::WHILE),
     ])
@@ -76,6 +116,17 @@ impl Token {
 }
 
 
+// `// ...` comments are thrown away by the parser, but the formatter needs them
+// back. `trailing` is set when the comment shares its line with some code,
+// e.g. `x = 1; // note`.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub trailing: bool,
+}
+
+
 // Example enum for Literal to replace Object
 #[derive(Debug, Clone)]
 pub enum Literal {
@@ -97,11 +148,19 @@ impl Literal {
 }
 
 // distinguished from Literal as this is interpreted rather than parsed. 
+// Lists, maps and callables are shared, not copied: two Values can point at
+// the same list, and changing it through one shows through the other.
 #[derive(Debug, Clone)]
 pub enum Value {
     String(String),
     Number(f64),
     Boolean(bool),
     Nil,
+    List(Arc<Mutex<Vec<Value>>>),
+    Map(Arc<Mutex<LoxMap>>),
+    Callable(Arc<dyn LoxCallable>),
+    // what range(start, end) gives back: the numbers from start up to but
+    // not including end, counted out when they're wanted
+    Range(f64, f64),
 }
 